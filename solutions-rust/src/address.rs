@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+use std::{borrow::Borrow, fmt, ops::Deref};
+
+/// Implements the string-like boilerplate shared by all
+/// the identifiers that show up in an envelope.
+macro_rules! string_id {
+    ($name:ident) => {
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(id: &str) -> Self {
+                Self(id.to_owned())
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(id: String) -> Self {
+                Self(id)
+            }
+        }
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.0 == *other
+            }
+        }
+
+        impl PartialEq<String> for $name {
+            fn eq(&self, other: &String) -> bool {
+                &self.0 == other
+            }
+        }
+    };
+}
+
+/// The id of a server node in the cluster, e.g. `n1`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NodeId(String);
+
+/// The id of a Maelstrom client, e.g. `c1`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ClientId(String);
+
+/// Anybody that can send or receive an envelope: a node,
+/// a client, or one of Maelstrom's services (e.g. `seq-kv`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Address(String);
+
+string_id!(NodeId);
+string_id!(ClientId);
+string_id!(Address);
+
+impl Address {
+    /// Whether this address belongs to a server node.
+    pub fn is_node(&self) -> bool {
+        self.0.starts_with('n')
+    }
+
+    /// Whether this address belongs to a Maelstrom client.
+    pub fn is_client(&self) -> bool {
+        self.0.starts_with('c')
+    }
+
+    /// The node behind this address, if it is one.
+    pub fn node_id(&self) -> Option<NodeId> {
+        self.is_node().then(|| NodeId(self.0.clone()))
+    }
+
+    /// The client behind this address, if it is one.
+    pub fn client_id(&self) -> Option<ClientId> {
+        self.is_client().then(|| ClientId(self.0.clone()))
+    }
+}
+
+impl From<NodeId> for Address {
+    fn from(id: NodeId) -> Self {
+        Self(id.0)
+    }
+}
+
+impl From<ClientId> for Address {
+    fn from(id: ClientId) -> Self {
+        Self(id.0)
+    }
+}
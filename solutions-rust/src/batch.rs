@@ -0,0 +1,98 @@
+use std::{cell::RefCell, thread};
+
+thread_local! {
+    /// What this thread has sent since its outermost [batched] started.
+    static BATCH: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+}
+
+/// Run `handle`, holding on to everything it sends (see [crate::Envelope::send])
+/// until it's done, and then write all of it to stdout at once: one lock, one
+/// write, one flush, e.g. for the `broadcast_ok`s of a burst of broadcasts.
+///
+/// Only sends from this thread are held back. Batches started inside a batch
+/// are part of the outer one. If `handle` panics, whatever it sent up to
+/// then still goes out, and the thread goes back to sending right away.
+pub fn batched<R>(handle: impl FnOnce() -> R) -> R {
+    let outermost = BATCH.with_borrow_mut(|batch| {
+        let outermost = batch.is_none();
+        batch.get_or_insert_with(Vec::new);
+        outermost
+    });
+    // Not `then_some`: that would make (and drop, and so flush) one anyway.
+    let _flush = if outermost { Some(Flush) } else { None };
+    handle()
+}
+
+/// Ends the outermost batch when dropped, unwinding or not.
+struct Flush;
+
+impl Drop for Flush {
+    fn drop(&mut self) {
+        let lines = BATCH.with_borrow_mut(Option::take).unwrap_or_default();
+        if lines.is_empty() {
+            return;
+        }
+        let written = crate::envelope::write_out(&lines);
+        // Panicking again while unwinding would abort.
+        if !thread::panicking() {
+            written.unwrap();
+        }
+    }
+}
+
+/// Hold on to `line` if we're in a batch. Returns whether we are.
+pub(crate) fn buffer_line(line: &[u8]) -> bool {
+    BATCH.with_borrow_mut(|batch| {
+        batch
+            .as_mut()
+            .map(|batch| batch.extend_from_slice(line))
+            .is_some()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capture::capturing, drain_outbound, Envelope};
+    use serde_json::{json, Value};
+    use std::panic;
+
+    fn echo(text: &str) -> Envelope<Value> {
+        Envelope::new("n1", "c1", None, json!({"type": "echo_ok", "echo": text}))
+    }
+
+    fn echoes() -> Vec<Value> {
+        drain_outbound()
+            .iter()
+            .map(|envelope| envelope.message()["echo"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn batch_goes_out_when_the_outermost_one_ends() {
+        let _capture = capturing();
+        batched(|| {
+            echo("a").send();
+            batched(|| echo("b").send());
+            assert!(echoes().is_empty(), "held back until the end");
+            echo("c").send();
+        });
+        assert_eq!(echoes(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn panic_inside_a_batch_still_sends_and_ends_it() {
+        let _capture = capturing();
+        let panicked = panic::catch_unwind(|| {
+            batched(|| {
+                echo("a").send();
+                panic!("handler fell over");
+            })
+        });
+        assert!(panicked.is_err());
+        assert_eq!(echoes(), ["a"]);
+
+        echo("b").send();
+        assert_eq!(echoes(), ["b"], "not stuck in a batch");
+    }
+}
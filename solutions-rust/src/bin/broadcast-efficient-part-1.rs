@@ -15,12 +15,12 @@ const STRIDE: usize = 2;
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum Message {
     Init {
-        node_id: String,
-        node_ids: Vec<String>,
+        node_id: NodeId,
+        node_ids: Vec<NodeId>,
     },
     InitOk,
     Topology {
-        topology: HashMap<String, Vec<String>>,
+        topology: HashMap<NodeId, Vec<NodeId>>,
     },
     TopologyOk,
     Broadcast {
@@ -39,6 +39,14 @@ pub enum Message {
     },
 }
 
+request_response_pairs!(Message {
+    Init => InitOk,
+    Topology => TopologyOk,
+    Broadcast => BroadcastOk,
+    Read => ReadOk,
+    Sync => SyncOk,
+});
+
 #[derive(Debug, Default)]
 pub struct RemoteNodeHandler {
     unacknowledged_messages: Vec<usize>,
@@ -60,20 +68,26 @@ impl RemoteNodeHandler {
 }
 
 pub fn main() {
+    let config = RuntimeConfig::from_env().unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
+    config.install().unwrap();
+    let sync_interval = config.sync_interval().unwrap_or(SYNC_INTERVAL);
+    let stride = config.stride.unwrap_or(STRIDE);
+
     let (tx, rx) = channel::<Envelope<Message>>();
 
     spawn(move || {
-        read_stdin(tx);
+        read_stdin(tx).unwrap();
     });
 
-    let mut remote_node_handlers: HashMap<String, RemoteNodeHandler> = Default::default();
-    let mut our_neighbors: Vec<String> = Default::default();
+    let mut remote_node_handlers: HashMap<NodeId, RemoteNodeHandler> = Default::default();
 
     let mut messages: HashSet<usize> = HashSet::new();
-    let mut all_node_ids: Vec<String> = Default::default();
-    let mut our_id = Default::default();
+    let mut ctx = NodeContext::new();
 
-    let mut deadline = Instant::now() + SYNC_INTERVAL;
+    let mut deadline = Instant::now() + sync_interval;
 
     loop {
         let should_wait_for_at_most = deadline - Instant::now();
@@ -81,36 +95,35 @@ pub fn main() {
             Ok(envelope) => {
                 match envelope.message() {
                     Message::Init { node_id, node_ids } => {
-                        our_id = node_id.to_owned();
-                        all_node_ids = node_ids.clone();
-                        for (_, node_id) in node_ids.iter().enumerate() {
+                        for node_id in node_ids.iter() {
                             remote_node_handlers.insert(node_id.clone(), RemoteNodeHandler::new());
                         }
-                        envelope.reply(Message::InitOk).send();
+                        ctx.accept_init(&envelope, node_id, node_ids, Message::InitOk);
                     }
 
                     Message::Topology { .. } => {
                         // Let's create a topology where
-                        // 1 of every STRIDE nodes of our cluster
+                        // 1 of every `stride` nodes of our cluster
                         // (except us) is our neighbor.
-                        let our_position = all_node_ids
-                            .iter()
-                            .position(|node_id| node_id == &our_id)
-                            .unwrap();
-
-                        our_neighbors = all_node_ids
-                            .iter()
-                            .skip((our_position + 1) % STRIDE)
-                            .step_by(STRIDE)
-                            .cloned()
-                            .collect();
-
-                        envelope.reply(Message::TopologyOk).send();
+                        let overlay = Topology::from_fn(ctx.all_nodes(), |node_id| {
+                            let position = ctx.position(node_id).unwrap();
+                            ctx.all_nodes()
+                                .iter()
+                                .skip((position + 1) % stride)
+                                .step_by(stride)
+                                .cloned()
+                                .collect()
+                        });
+                        if !overlay.is_connected() {
+                            eprintln!("Our overlay is partitioned, broadcasts won't converge.");
+                        }
+
+                        ctx.accept_topology(&envelope, overlay, Message::TopologyOk);
                     }
 
                     Message::Broadcast { message } => {
                         if messages.insert(*message) {
-                            for neighbor in &our_neighbors {
+                            for neighbor in ctx.neighbors() {
                                 remote_node_handlers
                                     .get_mut(neighbor)
                                     .unwrap()
@@ -133,7 +146,7 @@ pub fn main() {
                     Message::Sync { messages: inbound } => {
                         for &message in inbound {
                             if messages.insert(message) {
-                                for neighbor in &our_neighbors {
+                                for neighbor in ctx.neighbors() {
                                     remote_node_handlers
                                         .get_mut(neighbor)
                                         .unwrap()
@@ -152,7 +165,7 @@ pub fn main() {
                         messages: acknowledged_messages,
                     } => {
                         remote_node_handlers
-                            .get_mut(&envelope.src)
+                            .get_mut(envelope.src.as_str())
                             .unwrap()
                             .acknowledge_synced(acknowledged_messages);
                     }
@@ -170,7 +183,7 @@ pub fn main() {
                 .for_each(|(remote_node_id, remote_node_handler)| {
                     if !remote_node_handler.unacknowledged_messages.is_empty() {
                         Envelope::new(
-                            &our_id,
+                            ctx.id(),
                             remote_node_id,
                             None,
                             Message::Sync {
@@ -180,7 +193,7 @@ pub fn main() {
                         .send();
                     }
                 });
-            deadline += SYNC_INTERVAL;
+            deadline += sync_interval;
         }
     }
 }
@@ -14,12 +14,12 @@ const SYNC_INTERVAL: Duration = Duration::from_millis(250);
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum Message {
     Init {
-        node_id: String,
-        node_ids: Vec<String>,
+        node_id: NodeId,
+        node_ids: Vec<NodeId>,
     },
     InitOk,
     Topology {
-        topology: HashMap<String, Vec<String>>,
+        topology: HashMap<NodeId, Vec<NodeId>>,
     },
     TopologyOk,
     Broadcast {
@@ -38,6 +38,23 @@ pub enum Message {
     },
 }
 
+request_response_pairs!(Message {
+    Init => InitOk,
+    Topology => TopologyOk,
+    Broadcast => BroadcastOk,
+    Read => ReadOk,
+    Sync => SyncOk,
+});
+
+impl Respond for Message {
+    fn ok_for(&self, _in_reply_to: Option<usize>) -> Option<Self> {
+        match self {
+            Message::Broadcast { .. } => Some(Message::BroadcastOk),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct RemoteNodeHandler {
     unacknowledged_messages: Vec<usize>,
@@ -62,14 +79,22 @@ pub fn main() {
     let (tx, rx) = channel::<Envelope<Message>>();
 
     spawn(move || {
-        read_stdin(tx);
+        // A broadcast we've already taken in only needs acking again.
+        let inbound =
+            InboundChain::new().with_middleware(dedup(
+                |envelope: &Envelope<Message>| match envelope.message() {
+                    Message::Broadcast { message } => Some(*message),
+                    _ => None,
+                },
+            ));
+        read_stdin_through(tx, inbound).unwrap();
     });
 
-    let mut remote_node_handlers: HashMap<String, RemoteNodeHandler> = Default::default();
-    let mut our_neighbors: Vec<String> = Default::default();
+    let mut remote_node_handlers: HashMap<NodeId, RemoteNodeHandler> = Default::default();
 
     let mut messages: HashSet<usize> = HashSet::new();
-    let mut our_id = Default::default();
+    let mut ctx = NodeContext::new();
+    let mut outbound = PeerQueue::new();
 
     let mut deadline = Instant::now() + SYNC_INTERVAL;
 
@@ -79,21 +104,23 @@ pub fn main() {
         match rx.recv_timeout(should_wait_for_at_most) {
             Ok(envelope) => match envelope.message() {
                 Message::Init { node_id, node_ids } => {
-                    our_id = node_id.to_owned();
-                    for (_, node_id) in node_ids.iter().enumerate() {
+                    for node_id in node_ids.iter() {
                         remote_node_handlers.insert(node_id.clone(), RemoteNodeHandler::new());
                     }
-                    envelope.reply(Message::InitOk).send();
+                    ctx.accept_init(&envelope, node_id, node_ids, Message::InitOk);
                 }
 
                 Message::Topology { topology } => {
-                    our_neighbors = topology.get(&our_id).unwrap().clone();
-                    envelope.reply(Message::TopologyOk).send();
+                    ctx.accept_topology(
+                        &envelope,
+                        Topology::from(topology.clone()),
+                        Message::TopologyOk,
+                    );
                 }
 
                 Message::Broadcast { message } => {
                     if messages.insert(*message) {
-                        for neighbor in &our_neighbors {
+                        for neighbor in ctx.neighbors() {
                             remote_node_handlers
                                 .get_mut(neighbor)
                                 .unwrap()
@@ -116,7 +143,7 @@ pub fn main() {
                 Message::Sync { messages: inbound } => {
                     for &message in inbound {
                         if messages.insert(message) {
-                            for neighbor in &our_neighbors {
+                            for neighbor in ctx.neighbors() {
                                 remote_node_handlers
                                     .get_mut(neighbor)
                                     .unwrap()
@@ -135,7 +162,7 @@ pub fn main() {
                     messages: acknowledged_messages,
                 } => {
                     remote_node_handlers
-                        .get_mut(&envelope.src)
+                        .get_mut(envelope.src.as_str())
                         .unwrap()
                         .acknowledge_synced(acknowledged_messages);
                 }
@@ -151,17 +178,15 @@ pub fn main() {
                 .iter()
                 .for_each(|(remote_node_id, remote_node_handler)| {
                     if !remote_node_handler.unacknowledged_messages.is_empty() {
-                        Envelope::new(
-                            &our_id,
+                        outbound.push(
                             remote_node_id,
-                            None,
                             Message::Sync {
                                 messages: remote_node_handler.unacknowledged_messages.to_vec(),
                             },
-                        )
-                        .send();
+                        );
                     }
                 });
+            outbound.flush(ctx.id());
             deadline += SYNC_INTERVAL;
         }
     }
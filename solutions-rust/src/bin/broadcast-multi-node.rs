@@ -1,5 +1,5 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
     sync::mpsc::channel,
     thread::spawn,
     time::{Duration, Instant},
@@ -12,12 +12,12 @@ use serde::{Deserialize, Serialize};
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum Message {
     Init {
-        node_id: String,
-        node_ids: Vec<String>,
+        node_id: NodeId,
+        node_ids: Vec<NodeId>,
     },
     InitOk,
     Topology {
-        topology: HashMap<String, Vec<String>>,
+        topology: HashMap<NodeId, Vec<NodeId>>,
     },
     TopologyOk,
     Broadcast {
@@ -36,6 +36,14 @@ pub enum Message {
     },
 }
 
+request_response_pairs!(Message {
+    Init => InitOk,
+    Topology => TopologyOk,
+    Broadcast => BroadcastOk,
+    Read => ReadOk,
+    Sync => SyncOk,
+});
+
 #[derive(Debug, Default)]
 pub struct RemoteNodeHandler {
     unacknowledged_messages: Vec<usize>,
@@ -60,99 +68,95 @@ pub fn main() {
     let (tx, rx) = channel::<Envelope<Message>>();
 
     spawn(move || {
-        read_stdin(tx);
+        read_stdin(tx).unwrap();
     });
 
     const SYNC_INTERVAL: Duration = Duration::from_millis(250);
 
-    let mut remote_node_handlers: HashMap<String, RemoteNodeHandler> = Default::default();
-    let mut our_neighbors: Vec<String> = Default::default();
+    let mut remote_node_handlers: HashMap<NodeId, RemoteNodeHandler> = Default::default();
 
-    let mut messages: HashSet<usize> = HashSet::new();
-    let mut our_id = Default::default();
+    let mut messages: SeenSet<usize, FxBuildHasher> = SeenSet::default();
+    let mut ctx = NodeContext::new();
 
     let mut deadline = Instant::now() + SYNC_INTERVAL;
 
     loop {
         let should_wait_for_at_most = deadline - Instant::now();
-        match rx.recv_timeout(should_wait_for_at_most) {
-            Ok(envelope) => {
-                match envelope.message() {
-                    // Create new handlers for every node in the cluster.
-                    // This is for state-keeping for individual nodes using an actor pattern.
-                    Message::Init { node_id, node_ids } => {
-                        our_id = node_id.to_owned();
-                        for (_, node_id) in node_ids.iter().enumerate() {
-                            remote_node_handlers.insert(node_id.clone(), RemoteNodeHandler::new());
-                        }
-                        envelope.reply(Message::InitOk).send();
+        if let Ok(envelope) = rx.recv_timeout(should_wait_for_at_most) {
+            match envelope.message() {
+                // Create new handlers for every node in the cluster.
+                // This is for state-keeping for individual nodes using an actor pattern.
+                Message::Init { node_id, node_ids } => {
+                    for node_id in node_ids.iter() {
+                        remote_node_handlers.insert(node_id.clone(), RemoteNodeHandler::new());
                     }
+                    ctx.accept_init(&envelope, node_id, node_ids, Message::InitOk);
+                }
 
-                    // Set up our topology.
-                    Message::Topology { topology } => {
-                        our_neighbors = topology.get(&our_id).unwrap().clone();
-                        envelope.reply(Message::TopologyOk).send();
-                    }
+                // Set up our topology.
+                Message::Topology { topology } => {
+                    ctx.accept_topology(
+                        &envelope,
+                        Topology::from(topology.clone()),
+                        Message::TopologyOk,
+                    );
+                }
 
-                    // Standard broadcast from a client. Just write to our messages
-                    // and if we hadn't seen before, dump it to the buffer for every
-                    // remote server, so that it can be sent later.
-                    Message::Broadcast { message } => {
-                        if messages.insert(*message) {
-                            for neighbor in &our_neighbors {
-                                remote_node_handlers
-                                    .get_mut(neighbor)
-                                    .unwrap()
-                                    .send_message(*message);
-                            }
+                // Standard broadcast from a client. Just write to our messages
+                // and if we hadn't seen before, dump it to the buffer for every
+                // remote server, so that it can be sent later.
+                Message::Broadcast { message } => {
+                    let outcome = messages.insert_or_ack(*message, &envelope, Message::BroadcastOk);
+                    if outcome == InsertOutcome::New {
+                        for neighbor in ctx.neighbors() {
+                            remote_node_handlers
+                                .get_mut(neighbor)
+                                .unwrap()
+                                .send_message(*message);
                         }
-                        envelope.reply(Message::BroadcastOk).send();
-                    }
-                    // We're using a different channel of comms amongst
-                    // internal nodes so we won't reuse Broadcast.
-                    Message::BroadcastOk => {}
-                    Message::Read => {
-                        envelope
-                            .reply(Message::ReadOk {
-                                messages: messages.iter().copied().collect(),
-                            })
-                            .send();
                     }
-                    // Sync's are internal comms that servers use to populate local buffers
-                    // that get flushed periodically as a single message.
-                    Message::Sync { messages: inbound } => {
-                        for &message in inbound {
-                            if messages.insert(message) {
-                                for neighbor in &our_neighbors {
-                                    remote_node_handlers
-                                        .get_mut(neighbor)
-                                        .unwrap()
-                                        .send_message(message);
-                                }
-                            }
+                }
+                // We're using a different channel of comms amongst
+                // internal nodes so we won't reuse Broadcast.
+                Message::BroadcastOk => {}
+                Message::Read => {
+                    envelope
+                        .reply(Message::ReadOk {
+                            messages: messages.iter().copied().collect(),
+                        })
+                        .send();
+                }
+                // Sync's are internal comms that servers use to populate local buffers
+                // that get flushed periodically as a single message.
+                Message::Sync { messages: inbound } => {
+                    for message in messages.insert_all_returning_new(inbound.iter().copied()) {
+                        for neighbor in ctx.neighbors() {
+                            remote_node_handlers
+                                .get_mut(neighbor)
+                                .unwrap()
+                                .send_message(message);
                         }
-                        envelope
-                            .reply(Message::SyncOk {
-                                messages: inbound.to_vec(),
-                            })
-                            .send();
                     }
-                    // SyncOk's are internal messages from other servers
-                    // that we can use to mark some messages as acknowledged, in bulk,
-                    // for that given server.
-                    Message::SyncOk {
-                        messages: acknowledged_messages,
-                    } => {
-                        remote_node_handlers
-                            .get_mut(&envelope.src)
-                            .unwrap()
-                            .acknowledge_synced(acknowledged_messages);
-                    }
-
-                    _ => unimplemented!(),
+                    envelope
+                        .reply(Message::SyncOk {
+                            messages: inbound.to_vec(),
+                        })
+                        .send();
+                }
+                // SyncOk's are internal messages from other servers
+                // that we can use to mark some messages as acknowledged, in bulk,
+                // for that given server.
+                Message::SyncOk {
+                    messages: acknowledged_messages,
+                } => {
+                    remote_node_handlers
+                        .get_mut(envelope.src.as_str())
+                        .unwrap()
+                        .acknowledge_synced(acknowledged_messages);
                 }
+
+                _ => unimplemented!(),
             }
-            Err(_) => {}
         }
 
         // The buffer flush may be due, so take care of it.
@@ -162,7 +166,7 @@ pub fn main() {
                 .for_each(|(remote_node_id, remote_node_handler)| {
                     if !remote_node_handler.unacknowledged_messages.is_empty() {
                         Envelope::new(
-                            &our_id,
+                            ctx.id(),
                             remote_node_id,
                             None,
                             Message::Sync {
@@ -25,11 +25,18 @@ pub enum Message {
     },
 }
 
+request_response_pairs!(Message {
+    Init => InitOk,
+    Topology => TopologyOk,
+    Broadcast => BroadcastOk,
+    Read => ReadOk,
+});
+
 pub fn main() {
     let (tx, rx) = channel::<Envelope<Message>>();
 
     spawn(move || {
-        read_stdin(tx);
+        read_stdin(tx).unwrap();
     });
 
     let mut messages: Vec<usize> = vec![];
@@ -22,6 +22,11 @@ pub enum Message {
     },
 }
 
+request_response_pairs!(Message {
+    Init => InitOk,
+    Echo => EchoOk,
+});
+
 pub fn handle_message(rx: Receiver<Envelope<Message>>) {
     for msg in rx {
         match msg.message() {
@@ -36,10 +41,10 @@ pub fn handle_message(rx: Receiver<Envelope<Message>>) {
     }
 }
 
-pub fn main() {
+pub fn main() -> Result<(), RuntimeError> {
     let (tx, rx) = channel::<Envelope<Message>>();
 
     spawn(move || handle_message(rx));
 
-    read_stdin(tx);
+    read_stdin(tx)
 }
@@ -1,24 +1,21 @@
-use std::env;
-use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::{collections::HashMap, thread::spawn};
 
-use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::mpsc::{channel, Receiver};
 use serde::{Serialize, Deserialize};
-use maelstrom::{Body, Envelope, read_stdin};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use maelstrom::{Envelope, NodeContext, NodeId, OffsetMap, PollResult, RuntimeError, Topology, env_var, read_stdin, request_response_pairs};
 
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum Message {
     Init {
-        node_id: String,
-        node_ids: Vec<String>,
+        node_id: NodeId,
+        node_ids: Vec<NodeId>,
     },
     InitOk,
     Topology {
-        topology: HashMap<String, Vec<String>>,
+        topology: HashMap<NodeId, Vec<NodeId>>,
     },
     TopologyOk,
     Send {
@@ -29,23 +26,32 @@ pub enum Message {
         offset: usize
     },
     Poll {
-        offsets: HashMap<String, usize>
+        offsets: OffsetMap
     },
     PollOk {
-        msgs: HashMap<String, Vec<[usize; 2]>>
+        msgs: PollResult
     },
     CommitOffsets {
-        offsets: HashMap<String, usize>
+        offsets: OffsetMap
     },
     CommitOffsetsOk,
     ListCommittedOffsets {
         keys: Vec<String>
     },
     ListCommittedOffsetsOk {
-        offsets: HashMap<String, usize>
+        offsets: OffsetMap
     }
 }
 
+request_response_pairs!(Message {
+    Init => InitOk,
+    Topology => TopologyOk,
+    Send => SendOk,
+    Poll => PollOk,
+    CommitOffsets => CommitOffsetsOk,
+    ListCommittedOffsets => ListCommittedOffsetsOk,
+});
+
 
 #[derive(Debug, Default)]
 pub struct Log {
@@ -73,31 +79,12 @@ impl Log {
 }
 
 
-#[derive(Debug, Default)]
-pub struct NodeState {
-    pub id: String,
-    pub neighbors: Vec<String>,
-    pub all_nodes: Vec<String>
-}
-
-impl NodeState {
-
-    pub fn save_topology(&mut self, topology: &HashMap<String, Vec<String>>) {
-        self.neighbors = topology.get(&self.id).unwrap().clone();
-    }
-    pub fn save_nodes(&mut self, nodes: &[String]) {
-        self.all_nodes = nodes.to_vec();
-    }
-
-    pub fn set_id(&mut self, id: &String) {
-        self.id = id.to_owned();
-    }
-}
-
-
 #[derive(Debug, Default)]
 pub struct LogState {
-    pub logs: HashMap<String, Log>
+    pub logs: HashMap<String, Log>,
+    /// What to report for keys with no log here, if anything.
+    /// Without it, they're left out of `list_committed_offsets_ok`.
+    pub uncommitted_default: Option<usize>
 }
 
 impl LogState {
@@ -124,97 +111,139 @@ impl LogState {
     pub fn get_committed_offset(&self, key: &str) -> Option<usize> {
         self.logs.get(key).map(|log| log.committed_offset)
     }
+
+    pub fn committed_or(&self, key: &str, default: usize) -> usize {
+        self.get_committed_offset(key).unwrap_or(default)
+    }
+
+    /// The committed offsets for `keys`, with keys we've got nothing
+    /// for either defaulted or left out (see `uncommitted_default`).
+    pub fn list_committed_offsets(&self, keys: &[String]) -> OffsetMap {
+        keys
+        .iter()
+        .filter_map(|k| {
+            match self.uncommitted_default {
+                Some(default) => Some(self.committed_or(k, default)),
+                None => self.get_committed_offset(k)
+            }
+            .map(|offset| (k.clone(), offset))
+        })
+        .collect()
+    }
 }
 
 
-pub fn handle_message(rx: Receiver<Envelope<Message>>) {
+pub fn handle_message(rx: Receiver<Envelope<Message>>, uncommitted_default: Option<usize>) {
 
-    let mut node_state = NodeState::default();
-    let mut state: LogState = Default::default();
+    let mut ctx = NodeContext::new();
+    let mut state = LogState {
+        uncommitted_default,
+        ..Default::default()
+    };
 
     loop {
-        match rx.recv_timeout(Duration::from_millis(100)) {
-            Ok(envelope) => {
-                match envelope.message() {
-                    Message::Init { node_id, node_ids } => {
-                        node_state.set_id(node_id);
-                        node_state.save_nodes(node_ids);
-                        envelope.reply(Message::InitOk).send();
-                    },
-                    Message::Topology { topology } => {
-                        node_state.save_topology(topology);
-                        envelope.reply(Message::TopologyOk).send();
-                    },
-                    Message::Send { key, msg } => {
-
-                        let mut offset = 1;
-
-                        state
-                        .logs
-                        .entry(key.clone())
-                        .and_modify(|log| {
-                            offset = log.append(*msg);
-                        })
-                        .or_insert(Log::new(&[*msg]));
-
-                        envelope.reply(Message::SendOk { offset }).send();
-                    },
-                    Message::Poll { offsets } => {
-                        let poll_ok_body: HashMap<String, Vec<[usize; 2]>> = 
-                            offsets
-                            .into_iter()
-                            .filter_map(|(key, offset)| {
-                                state
-                                .get_messages_from_offset(key, *offset)
-                                .map(|ofsts| {
-                                    (key.clone(), ofsts)
-                                })
-                            })
-                            .collect();
-                        envelope.reply(Message::PollOk { msgs: poll_ok_body }).send();
-                    },
-                    Message::CommitOffsets { offsets } => {
+        if let Ok(envelope) = rx.recv_timeout(Duration::from_millis(100)) {
+            match envelope.message() {
+                Message::Init { node_id, node_ids } => {
+                    ctx.accept_init(&envelope, node_id, node_ids, Message::InitOk);
+                },
+                Message::Topology { topology } => {
+                    ctx.accept_topology(&envelope, Topology::from(topology.clone()), Message::TopologyOk);
+                },
+                Message::Send { key, msg } => {
+
+                    let mut offset = 1;
+
+                    state
+                    .logs
+                    .entry(key.clone())
+                    .and_modify(|log| {
+                        offset = log.append(*msg);
+                    })
+                    .or_insert(Log::new(&[*msg]));
+
+                    envelope.reply(Message::SendOk { offset }).send();
+                },
+                Message::Poll { offsets } => {
+                    let poll_ok_body: PollResult =
                         offsets
-                        .into_iter()
-                        .for_each(|(key, offset)| {
-                            state.commit_offset(key, *offset);
-                        });
-                        envelope.reply(Message::CommitOffsetsOk).send();
-                    },
-                    Message::ListCommittedOffsets { keys } => {
-                        
-                        let list_committed_offsets_ok_body: HashMap<String, usize> =
-                        keys
-                        .into_iter()
-                        .filter_map(|k| {
+                        .entries()
+                        .filter_map(|(key, offset)| {
                             state
-                            .get_committed_offset(k)
-                            .map(|offset| (k.clone(), offset))
+                            .get_messages_from_offset(key, offset)
+                            .map(|ofsts| {
+                                (key.to_owned(), ofsts)
+                            })
                         })
                         .collect();
-
-                        envelope.reply(Message::ListCommittedOffsetsOk { offsets: list_committed_offsets_ok_body }).send();
-                    },
-                    _ => {}
-                }
-            },
-
-            Err(err) => {
-            },
-
-
+                    envelope.reply(Message::PollOk { msgs: poll_ok_body }).send();
+                },
+                Message::CommitOffsets { offsets } => {
+                    offsets
+                    .entries()
+                    .for_each(|(key, offset)| {
+                        state.commit_offset(key, offset);
+                    });
+                    envelope.reply(Message::CommitOffsetsOk).send();
+                },
+                Message::ListCommittedOffsets { keys } => {
+                    
+                    let list_committed_offsets_ok_body = state.list_committed_offsets(keys);
+
+                    envelope.reply(Message::ListCommittedOffsetsOk { offsets: list_committed_offsets_ok_body }).send();
+                },
+                _ => {}
+            }
         }
     }
 }
 
 
-pub fn main() {
+pub fn main() -> Result<(), RuntimeError> {
+
+    // Off unless asked for, e.g. UNCOMMITTED_OFFSET_DEFAULT=0.
+    let uncommitted_default = env_var("UNCOMMITTED_OFFSET_DEFAULT")
+        .unwrap_or_else(|err| {
+            eprintln!("{err}");
+            std::process::exit(1);
+        });
 
     let (tx, rx) = channel();
 
     spawn(move || {
-        handle_message(rx);       
+        handle_message(rx, uncommitted_default);       
     });
 
-    read_stdin(tx);
-}
\ No newline at end of file
+    read_stdin(tx)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(uncommitted_default: Option<usize>) -> LogState {
+        let mut logs = HashMap::new();
+        logs.insert("k1".to_string(), Log::new(&[10, 11, 12]));
+        LogState {
+            logs,
+            uncommitted_default
+        }
+    }
+
+    fn keys() -> Vec<String> {
+        vec!["k1".to_string(), "never-written".to_string()]
+    }
+
+    #[test]
+    fn uncommitted_keys_get_the_default_when_asked_for() {
+        let offsets = state(Some(0)).list_committed_offsets(&keys());
+        assert_eq!(offsets, OffsetMap::new().with("k1", 3).with("never-written", 0));
+    }
+
+    #[test]
+    fn uncommitted_keys_are_left_out_otherwise() {
+        let offsets = state(None).list_committed_offsets(&keys());
+        assert_eq!(offsets, OffsetMap::new().with("k1", 3));
+    }
+}
@@ -1,8 +1,5 @@
 use std::{
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        mpsc::{channel, Receiver},
-    },
+    sync::mpsc::{channel, Receiver},
     thread::spawn,
 };
 
@@ -13,44 +10,58 @@ use serde::{Deserialize, Serialize};
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum Message {
     Init {
-        node_id: String,
-        node_ids: Vec<String>,
+        node_id: NodeId,
+        node_ids: Vec<NodeId>,
     },
     InitOk,
     Generate,
     GenerateOk {
-        id: String,
+        id: UniqueId,
     },
 }
 
-pub fn handle_message(rx: Receiver<Envelope<Message>>) {
-    let mut our_id = None;
-    let id = AtomicUsize::new(0);
+request_response_pairs!(Message {
+    Init => InitOk,
+    Generate => GenerateOk,
+});
+
+pub fn handle_message(rx: Receiver<Envelope<Message>>, format: IdFormat) {
+    let mut ids = None;
 
     for msg in rx {
         match msg.message() {
-            Message::Init { node_id, .. } => {
-                our_id = Some(node_id.clone());
+            Message::Init { node_id, node_ids } => {
+                let allocator = IdAllocator::new(format, node_id, node_ids)
+                    .unwrap_or_else(|err| panic!("Can't hand out unique ids: {err}"));
+                ids = Some(allocator);
                 msg.reply(Message::InitOk).send();
             }
 
-            Message::Generate => {
-                let id = format!(
-                    "{}-{}",
-                    our_id.as_ref().unwrap(),
-                    id.fetch_add(1, Ordering::SeqCst)
-                );
-                msg.reply(Message::GenerateOk { id }).send();
-            }
+            Message::Generate => match ids.as_mut().unwrap().next_id() {
+                Some(id) => msg.reply(Message::GenerateOk { id }).send(),
+                None => msg
+                    .defer()
+                    .reply_error(ErrorCode::Abort, "Ran out of ids")
+                    .send(),
+            },
             _ => {}
         }
     }
 }
 
-pub fn main() {
+pub fn main() -> Result<(), RuntimeError> {
+    // e.g. ID_FORMAT=uuid, or ID_FORMAT=numeric:8:32.
+    let format = match env_var("ID_FORMAT") {
+        Ok(format) => format.unwrap_or_default(),
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+
     let (tx, rx) = channel::<Envelope<Message>>();
 
-    spawn(move || handle_message(rx));
+    spawn(move || handle_message(rx, format));
 
-    read_stdin(tx);
+    read_stdin(tx)
 }
@@ -0,0 +1,109 @@
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::fmt;
+
+/// A number that's only valid within `MIN..=MAX`. Use it in message
+/// definitions so out-of-range values are rejected while decoding, and
+/// surface as a [crate::MalformedRequest] instead of being silently accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(transparent)]
+pub struct BoundedU64<const MIN: u64, const MAX: u64>(u64);
+
+impl<const MIN: u64, const MAX: u64> BoundedU64<MIN, MAX> {
+    pub fn get(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A value that didn't fit in its bounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfRange {
+    pub value: u64,
+    pub min: u64,
+    pub max: u64,
+}
+
+impl fmt::Display for OutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is out of range, expected a value in {}..={}",
+            self.value, self.min, self.max
+        )
+    }
+}
+
+impl std::error::Error for OutOfRange {}
+
+impl<const MIN: u64, const MAX: u64> TryFrom<u64> for BoundedU64<MIN, MAX> {
+    type Error = OutOfRange;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        if (MIN..=MAX).contains(&value) {
+            Ok(Self(value))
+        } else {
+            Err(OutOfRange {
+                value,
+                min: MIN,
+                max: MAX,
+            })
+        }
+    }
+}
+
+impl<const MIN: u64, const MAX: u64> From<BoundedU64<MIN, MAX>> for u64 {
+    fn from(value: BoundedU64<MIN, MAX>) -> Self {
+        value.0
+    }
+}
+
+impl<'de, const MIN: u64, const MAX: u64> Deserialize<'de> for BoundedU64<MIN, MAX> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Negative numbers (or anything that isn't one) already fail here.
+        let value = u64::deserialize(deserializer)?;
+        Self::try_from(value).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Envelope, ErrorCode, Strictness};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Message {
+        Add { delta: BoundedU64<0, 100> },
+    }
+
+    fn add(delta: &str) -> String {
+        format!(r#"{{"src":"c1","dest":"n1","body":{{"type":"add","msg_id":4,"delta":{delta}}}}}"#)
+    }
+
+    #[test]
+    fn in_range_deltas_decode() {
+        let envelope = Envelope::<Message>::parse(&add("100"), Strictness::Lenient).unwrap();
+        let Message::Add { delta } = envelope.message();
+        assert_eq!(delta.get(), 100);
+    }
+
+    #[test]
+    fn out_of_range_deltas_are_malformed() {
+        for delta in ["101", "-1", "18446744073709551616", "1.5"] {
+            let malformed =
+                Envelope::<Message>::parse(&add(delta), Strictness::Lenient).unwrap_err();
+            let reply = malformed.reply().expect("we know who sent it");
+            assert_eq!(reply.dest, "c1");
+            assert_eq!(reply.in_reply_to(), Some(4));
+            assert_eq!(reply.message().code, ErrorCode::MalformedRequest);
+        }
+    }
+
+    #[test]
+    fn the_bounds_are_in_the_error() {
+        let err = BoundedU64::<0, 100>::try_from(101).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "101 is out of range, expected a value in 0..=100"
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use crate::NodeId;
+use std::collections::{HashMap, HashSet};
+
+/// A balanced tree overlay over the cluster for spreading broadcasts.
+///
+/// Every node sorts the cluster's node ids the same way, so they all agree
+/// on the shape of the tree without talking to each other. A message we
+/// hear about for the first time is queued for every tree neighbor (our
+/// parent and children) except whoever told us, and stays queued for a
+/// neighbor until they acknowledge it, so it can be re-sent periodically.
+#[derive(Debug, Default)]
+pub struct BroadcastTree {
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+
+    /// Every message we've seen so far.
+    messages: HashSet<usize>,
+
+    /// Messages each neighbor hasn't acknowledged yet.
+    unacknowledged: HashMap<NodeId, Vec<usize>>,
+}
+
+impl BroadcastTree {
+    /// Build the overlay where every node has at most `fanout` children.
+    pub fn new(our_id: &NodeId, node_ids: &[NodeId], fanout: usize) -> Self {
+        let mut node_ids = node_ids.to_vec();
+        node_ids.sort();
+
+        let fanout = fanout.max(1);
+        let Some(our_position) = node_ids.iter().position(|node_id| node_id == our_id) else {
+            return Default::default();
+        };
+
+        let parent = (our_position > 0).then(|| node_ids[(our_position - 1) / fanout].clone());
+        let children: Vec<NodeId> = node_ids
+            .iter()
+            .skip(our_position * fanout + 1)
+            .take(fanout)
+            .cloned()
+            .collect();
+
+        let unacknowledged = parent
+            .iter()
+            .chain(children.iter())
+            .map(|neighbor| (neighbor.clone(), Vec::new()))
+            .collect();
+
+        Self {
+            parent,
+            children,
+            messages: HashSet::new(),
+            unacknowledged,
+        }
+    }
+
+    pub fn parent(&self) -> Option<&NodeId> {
+        self.parent.as_ref()
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    /// Our parent (if we're not the root) and our children.
+    pub fn neighbors(&self) -> impl Iterator<Item = &NodeId> {
+        self.parent.iter().chain(self.children.iter())
+    }
+
+    /// Every message we've seen so far.
+    pub fn messages(&self) -> &HashSet<usize> {
+        &self.messages
+    }
+
+    /// Record a message that a client (`from` is `None`) or a neighbor told us about.
+    /// Returns whether it's new to us, in which case it's queued for all the other neighbors.
+    pub fn receive(&mut self, message: usize, from: Option<&NodeId>) -> bool {
+        if !self.messages.insert(message) {
+            return false;
+        }
+        for (neighbor, unacknowledged) in self.unacknowledged.iter_mut() {
+            if Some(neighbor) != from {
+                unacknowledged.push(message);
+            }
+        }
+        true
+    }
+
+    /// A neighbor confirmed it has these messages, so stop sending them.
+    pub fn acknowledge(&mut self, from: &NodeId, messages: &[usize]) {
+        if let Some(unacknowledged) = self.unacknowledged.get_mut(from) {
+            unacknowledged.retain(|message| !messages.contains(message));
+        }
+    }
+
+    /// What still needs to go out to each neighbor.
+    pub fn pending(&self) -> impl Iterator<Item = (&NodeId, &[usize])> {
+        self.unacknowledged
+            .iter()
+            .filter(|(_, unacknowledged)| !unacknowledged.is_empty())
+            .map(|(neighbor, unacknowledged)| (neighbor, unacknowledged.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 25 node cluster gossiping in rounds: each round, every node sends
+    /// each neighbor whatever it still owes them, and hears back about it
+    /// in the same round, unless `drop` says the sync was lost.
+    struct Cluster {
+        ids: Vec<NodeId>,
+        trees: Vec<BroadcastTree>,
+        syncs: usize,
+        /// Messages that made it across an edge, counted one by one.
+        deliveries: usize,
+    }
+
+    impl Cluster {
+        fn new(size: usize, fanout: usize) -> Self {
+            let ids: Vec<NodeId> = (0..size).map(|n| format!("n{n}").into()).collect();
+            let trees = ids
+                .iter()
+                .map(|id| BroadcastTree::new(id, &ids, fanout))
+                .collect();
+            Self {
+                ids,
+                trees,
+                syncs: 0,
+                deliveries: 0,
+            }
+        }
+
+        fn index_of(&self, id: &NodeId) -> usize {
+            self.ids.iter().position(|other| other == id).unwrap()
+        }
+
+        fn round(&mut self, mut drop: impl FnMut() -> bool) {
+            let mut outbox = Vec::new();
+            for (from, tree) in self.trees.iter().enumerate() {
+                for (to, messages) in tree.pending() {
+                    outbox.push((from, self.index_of(to), messages.to_vec()));
+                }
+            }
+            for (from, to, messages) in outbox {
+                self.syncs += 1;
+                if drop() {
+                    continue;
+                }
+                self.deliveries += messages.len();
+                let from_id = self.ids[from].clone();
+                for &message in &messages {
+                    self.trees[to].receive(message, Some(&from_id));
+                }
+                let to_id = self.ids[to].clone();
+                self.trees[from].acknowledge(&to_id, &messages);
+            }
+        }
+
+        fn everybody_has(&self, message: usize) -> bool {
+            self.trees
+                .iter()
+                .all(|tree| tree.messages().contains(&message))
+        }
+    }
+
+    #[test]
+    fn the_tree_spans_every_node_once() {
+        let cluster = Cluster::new(25, 4);
+        let roots = cluster.trees.iter().filter(|tree| tree.parent().is_none());
+        assert_eq!(roots.count(), 1);
+        let edges: usize = cluster.trees.iter().map(|tree| tree.children().len()).sum();
+        assert_eq!(edges, 24);
+        for (index, tree) in cluster.trees.iter().enumerate() {
+            for child in tree.children() {
+                let child = &cluster.trees[cluster.index_of(child)];
+                assert_eq!(child.parent(), Some(&cluster.ids[index]));
+            }
+        }
+    }
+
+    #[test]
+    fn broadcasts_reach_everybody_quickly_and_cheaply() {
+        let mut cluster = Cluster::new(25, 4);
+        // 25 nodes with 4 children each make a tree 3 levels deep,
+        // so nothing's ever more than 6 hops away.
+        for message in 0..50 {
+            let entry = (message * 7) % 25;
+            cluster.trees[entry].receive(message, None);
+            for _ in 0..6 {
+                cluster.round(|| false);
+            }
+            assert!(cluster.everybody_has(message), "{message} took too long");
+        }
+        // Every message crossed every one of the 24 edges exactly once.
+        assert_eq!(cluster.deliveries, 50 * 24);
+        assert!(cluster.syncs <= 50 * 24);
+        assert!(cluster
+            .trees
+            .iter()
+            .all(|tree| tree.pending().next().is_none()));
+    }
+
+    #[test]
+    fn lost_syncs_get_sent_again() {
+        let mut cluster = Cluster::new(25, 4);
+        for message in 0..10 {
+            cluster.trees[message].receive(message, None);
+        }
+        let mut sent = 0;
+        let mut rounds = 0;
+        while cluster
+            .trees
+            .iter()
+            .any(|tree| tree.pending().next().is_some())
+        {
+            // Lose every third sync.
+            cluster.round(|| {
+                sent += 1;
+                sent % 3 == 0
+            });
+            rounds += 1;
+            assert!(rounds < 50, "never settled");
+        }
+        assert!((0..10).all(|message| cluster.everybody_has(message)));
+    }
+}
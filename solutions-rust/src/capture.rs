@@ -0,0 +1,135 @@
+use crate::{Envelope, Framer};
+use serde_json::Value;
+use std::sync::Mutex;
+
+static CAPTURED: Mutex<Option<Vec<Envelope<Value>>>> = Mutex::new(None);
+
+/// From now on, keep everything we send (see [Envelope::send]) to be
+/// picked up with [drain_outbound], instead of writing it to stdout.
+///
+/// There's no writer thread to race with: sending happens right there on
+/// the caller's thread, so once `send` returns, the envelope is in the
+/// buffer, and a check right after it is guaranteed to see it.
+pub fn capture_outbound() {
+    CAPTURED.lock().unwrap().get_or_insert_with(Vec::new);
+}
+
+/// Everything sent since we started capturing (or drained last), oldest first.
+/// Empty if we aren't capturing at all.
+pub fn drain_outbound() -> Vec<Envelope<Value>> {
+    CAPTURED
+        .lock()
+        .unwrap()
+        .as_mut()
+        .map(std::mem::take)
+        .unwrap_or_default()
+}
+
+/// Keep every envelope in `lines` (framed however stdout
+/// is) if we're capturing. Returns whether we are.
+pub(crate) fn capture_lines(mut lines: &[u8]) -> bool {
+    let mut captured = CAPTURED.lock().unwrap();
+    let Some(captured) = captured.as_mut() else {
+        return false;
+    };
+    #[cfg(test)]
+    CAPTURED_BYTES
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .extend_from_slice(lines);
+    let framing = crate::config::framing();
+    while let Some(frame) = framing.read_frame(&mut lines).unwrap() {
+        captured.push(serde_json::from_slice(&frame).unwrap());
+    }
+    true
+}
+
+/// Everything [capture_lines] was handed, byte for byte, for the
+/// tests that care about exactly what would have hit stdout.
+#[cfg(test)]
+static CAPTURED_BYTES: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+/// Like [drain_outbound], but the bytes as they were written, framing and all.
+#[cfg(test)]
+pub(crate) fn drain_outbound_bytes() -> Vec<u8> {
+    std::mem::take(
+        &mut CAPTURED_BYTES
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner),
+    )
+}
+
+/// Serializes the tests that look at what got sent, as there's only the
+/// one capture buffer for the whole process, and the tests that change
+/// how sending works for the whole process (see [crate::RuntimeConfig::install]).
+#[cfg(test)]
+static CAPTURING: Mutex<()> = Mutex::new(());
+
+/// Captures everything sent for as long as it's around (see [capturing]).
+#[cfg(test)]
+pub(crate) struct CaptureGuard {
+    _capturing: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+impl Drop for CaptureGuard {
+    fn drop(&mut self) {
+        *CAPTURED
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = None;
+    }
+}
+
+/// Start capturing from a clean slate, and keep every other
+/// test that sends anything waiting until we're done.
+#[cfg(test)]
+pub(crate) fn capturing() -> CaptureGuard {
+    let guard = CaptureGuard {
+        _capturing: CAPTURING
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner),
+    };
+    *CAPTURED
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(Vec::new());
+    drain_outbound_bytes();
+    guard
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn drain_hands_back_exactly_what_was_sent() {
+        let _capture = capturing();
+        let first = Envelope::new(
+            "n1",
+            "c1",
+            Some(1),
+            json!({"type": "echo_ok", "echo": "hi"}),
+        );
+        let second = Envelope::new(
+            "n1",
+            "n2",
+            None,
+            json!({"type": "sync", "messages": [1, 2]}),
+        );
+        first.send();
+        second.send();
+
+        let drained: Vec<String> = drain_outbound()
+            .iter()
+            .map(|envelope| serde_json::to_string(envelope).unwrap())
+            .collect();
+        assert_eq!(
+            drained,
+            [
+                serde_json::to_string(&first).unwrap(),
+                serde_json::to_string(&second).unwrap()
+            ]
+        );
+        assert!(drain_outbound().is_empty(), "draining empties the buffer");
+    }
+}
@@ -0,0 +1,294 @@
+use crate::Framing;
+use serde::Deserialize;
+use std::{
+    fmt, io,
+    path::PathBuf,
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// Where [RuntimeConfig::from_env] looks for the path to a config file.
+pub const CONFIG_PATH_VAR: &str = "MAELSTROM_CONFIG";
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+static CRLF: AtomicBool = AtomicBool::new(false);
+static LENGTH_PREFIXED: AtomicBool = AtomicBool::new(false);
+
+/// What ends every line we write to stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    /// `\n`, which is what Maelstrom expects.
+    #[default]
+    Lf,
+    /// `\r\n`, for tooling that insists on it.
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            Self::Lf => b"\n",
+            Self::CrLf => b"\r\n",
+        }
+    }
+}
+
+/// Why [RuntimeConfig::from_env] couldn't come up with a config.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file couldn't be read.
+    Read { path: String, err: io::Error },
+    /// The config file isn't a config.
+    Parse {
+        path: String,
+        err: serde_json::Error,
+    },
+    /// An env var (see [env_var]) is set to something that doesn't parse.
+    EnvVar {
+        name: String,
+        value: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read { path, err } => write!(f, "Failed to read config {path}: {err}"),
+            Self::Parse { path, err } => write!(f, "Failed to parse config {path}: {err}"),
+            Self::EnvVar {
+                name,
+                value,
+                reason,
+            } => write!(f, "Bad {name}={value:?}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Knobs for how the whole process behaves,
+/// as opposed to any one node's logic.
+///
+/// Besides these, it carries a few tunables that nodes may pick up
+/// instead of their hard-coded defaults, so that they can be tweaked
+/// between runs without recompiling.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RuntimeConfig {
+    /// Handle everything as usual, but don't write anything to stdout,
+    /// e.g. to replay a captured trace just to check that every message
+    /// decodes and nothing panics. Outbound messages still get counted.
+    pub dry_run: bool,
+
+    /// How many of the latest envelopes (in and out) to keep around
+    /// for [crate::history]. Zero, the default, keeps none.
+    pub history_capacity: usize,
+
+    /// Leave this alone when running under Maelstrom.
+    pub line_ending: LineEnding,
+
+    /// How messages are told apart on stdin and stdout. Leave
+    /// this alone when running under Maelstrom, too.
+    pub framing: Framing,
+
+    /// Copy every line we read or write to this file too, prefixed
+    /// with `IN`/`OUT` and a timestamp. Stdout is left alone.
+    pub tap_path: Option<PathBuf>,
+
+    /// How often to sync with our neighbors.
+    pub sync_interval_ms: Option<u64>,
+
+    /// How spread out a custom overlay should be.
+    pub stride: Option<usize>,
+}
+
+impl RuntimeConfig {
+    /// Read the json config file that [CONFIG_PATH_VAR] points to, if
+    /// it's set. Anything the file leaves out keeps its default.
+    ///
+    /// Fails if the file can't be read or doesn't make sense; better
+    /// that than to silently run with settings nobody asked for.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let Ok(path) = std::env::var(CONFIG_PATH_VAR) else {
+            return Ok(Default::default());
+        };
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) => return Err(ConfigError::Read { path, err }),
+        };
+        serde_json::from_str(&contents).map_err(|err| ConfigError::Parse { path, err })
+    }
+
+    pub fn sync_interval(&self) -> Option<Duration> {
+        self.sync_interval_ms.map(Duration::from_millis)
+    }
+
+    /// Make this the config for the rest of the process.
+    /// Fails if there's a tap file, and it can't be opened.
+    pub fn install(&self) -> io::Result<()> {
+        DRY_RUN.store(self.dry_run, Ordering::SeqCst);
+        CRLF.store(self.line_ending == LineEnding::CrLf, Ordering::SeqCst);
+        LENGTH_PREFIXED.store(self.framing == Framing::LengthPrefixed, Ordering::SeqCst);
+        crate::history::set_capacity(self.history_capacity);
+        crate::tap::open(self.tap_path.as_deref())
+    }
+}
+
+/// The env var `name` as a `T`, if it's set, e.g. for a knob only one
+/// node has. Something that doesn't parse is an error, rather than the
+/// default, so that a typo doesn't go unnoticed.
+pub fn env_var<T>(name: &str) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let Ok(value) = std::env::var(name) else {
+        return Ok(None);
+    };
+    match value.parse() {
+        Ok(parsed) => Ok(Some(parsed)),
+        Err(err) => Err(ConfigError::EnvVar {
+            name: name.to_owned(),
+            reason: err.to_string(),
+            value,
+        }),
+    }
+}
+
+pub(crate) fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+pub(crate) fn line_ending() -> LineEnding {
+    if CRLF.load(Ordering::Relaxed) {
+        LineEnding::CrLf
+    } else {
+        LineEnding::Lf
+    }
+}
+
+pub(crate) fn framing() -> Framing {
+    if LENGTH_PREFIXED.load(Ordering::Relaxed) {
+        Framing::LengthPrefixed
+    } else {
+        Framing::Lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Serializes the tests that point [CONFIG_PATH_VAR] somewhere.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// [RuntimeConfig::from_env] with a config file that says `contents`,
+    /// or with one that isn't there at all.
+    fn from_file(name: &str, contents: Option<&str>) -> Result<RuntimeConfig, ConfigError> {
+        let _env = ENV
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let path = crate::temp_path(name);
+        if let Some(contents) = contents {
+            std::fs::write(&path, contents).unwrap();
+        }
+        std::env::set_var(CONFIG_PATH_VAR, &path);
+        let config = RuntimeConfig::from_env();
+        std::env::remove_var(CONFIG_PATH_VAR);
+        let _ = std::fs::remove_file(&path);
+        config
+    }
+
+    #[test]
+    fn defaults_are_what_maelstrom_expects() {
+        let config = {
+            let _env = ENV
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            RuntimeConfig::from_env().unwrap()
+        };
+        assert_eq!(config, RuntimeConfig::default());
+        assert!(!config.dry_run);
+        assert_eq!(config.history_capacity, 0);
+        assert_eq!(config.line_ending, LineEnding::Lf);
+        assert_eq!(config.framing, Framing::Lines);
+        assert_eq!(config.tap_path, None);
+        assert_eq!(config.sync_interval(), None);
+        assert_eq!(config.stride, None);
+
+        assert_eq!(from_file("empty.json", Some("{}")).unwrap(), config);
+    }
+
+    #[test]
+    fn the_file_overrides_only_what_it_sets() {
+        let config = from_file(
+            "overrides.json",
+            Some(r#"{"dry_run": true, "line_ending": "crlf", "sync_interval_ms": 50}"#),
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            RuntimeConfig {
+                dry_run: true,
+                line_ending: LineEnding::CrLf,
+                sync_interval_ms: Some(50),
+                ..Default::default()
+            }
+        );
+        assert_eq!(config.sync_interval(), Some(Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn a_bad_or_missing_file_is_reported() {
+        let err = from_file("malformed.json", Some(r#"{"dry_run": "yes"#)).unwrap_err();
+        assert!(matches!(err, ConfigError::Parse { .. }), "{err:?}");
+        assert!(err.to_string().contains("malformed.json"), "{err}");
+
+        let err = from_file("typo.json", Some(r#"{"line_ending": "cr"}"#)).unwrap_err();
+        assert!(matches!(err, ConfigError::Parse { .. }), "{err:?}");
+
+        let err = from_file("missing.json", None).unwrap_err();
+        assert!(matches!(err, ConfigError::Read { .. }), "{err:?}");
+    }
+
+    #[test]
+    fn env_vars_that_dont_parse_are_reported() {
+        // Nobody else reads these.
+        std::env::set_var("MAELSTROM_TEST_GOOD", "7");
+        std::env::set_var("MAELSTROM_TEST_BAD", "seven");
+        assert_eq!(env_var::<usize>("MAELSTROM_TEST_GOOD").unwrap(), Some(7));
+        assert_eq!(env_var::<usize>("MAELSTROM_TEST_UNSET").unwrap(), None);
+        let err = env_var::<usize>("MAELSTROM_TEST_BAD").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Bad MAELSTROM_TEST_BAD=\"seven\": invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn dry_runs_write_nothing_but_still_record_the_send() {
+        let _capture = crate::capture::capturing();
+        RuntimeConfig {
+            dry_run: true,
+            history_capacity: 8,
+            ..Default::default()
+        }
+        .install()
+        .unwrap();
+        let envelope =
+            crate::Envelope::new("n1", "c1", Some(1), serde_json::json!({"type": "echo_ok"}));
+        envelope.send();
+        let history = crate::history();
+        RuntimeConfig::default().install().unwrap();
+
+        assert!(crate::drain_outbound().is_empty());
+        assert!(crate::capture::drain_outbound_bytes().is_empty());
+        assert!(history
+            .sent()
+            .any(|sent| sent.msg_id() == envelope.msg_id() && sent.dest == "c1"));
+    }
+}
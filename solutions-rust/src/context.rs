@@ -0,0 +1,591 @@
+use crate::{
+    Clock, Envelope, History, Metrics, NodeId, SystemClock, Timer, Topology, TopologyStrategy,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// A message with a `server_ts` tacked on (see [NodeContext::reply_timestamped]).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Timestamped<M> {
+    pub server_ts: u64,
+    #[serde(flatten)]
+    pub message: M,
+}
+
+/// The `init` and `topology` messages (and their acks) of whatever message
+/// type the node uses, for [NodeContext::handle_setup] to take care of.
+pub trait SetupMessages: Sized {
+    /// Our own id and every node's, if this is an `init`.
+    fn as_init(&self) -> Option<(&NodeId, &[NodeId])>;
+    /// The topology, if this is a `topology`.
+    fn as_topology(&self) -> Option<Topology>;
+    fn init_ok() -> Self;
+    fn topology_ok() -> Self;
+}
+
+/// Gets to swap the topology we're given for one of our own (see
+/// [NodeContext::with_topology_hook]), e.g. a spanning tree over it.
+pub type TopologyHook<C> = Box<dyn FnMut(&NodeContext<C>, Topology) -> Topology + Send>;
+
+/// What a node knows about itself and the cluster it's a part of.
+#[derive(Default)]
+pub struct NodeContext<C = SystemClock> {
+    id: NodeId,
+    all_nodes: Vec<NodeId>,
+    metrics: Metrics,
+    topology: Topology,
+    /// What to go with until we're given a topology.
+    topology_strategy: TopologyStrategy,
+    clock: C,
+    initialized_at: Option<Instant>,
+    init_msg_id: Option<usize>,
+    topology_hook: Option<TopologyHook<C>>,
+}
+
+impl<C: fmt::Debug> fmt::Debug for NodeContext<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NodeContext")
+            .field("id", &self.id)
+            .field("all_nodes", &self.all_nodes)
+            .field("metrics", &self.metrics)
+            .field("topology", &self.topology)
+            .field("topology_strategy", &self.topology_strategy)
+            .field("clock", &self.clock)
+            .field("initialized_at", &self.initialized_at)
+            .field("init_msg_id", &self.init_msg_id)
+            .field("topology_hook", &self.topology_hook.is_some())
+            .finish()
+    }
+}
+
+impl NodeContext {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<C: Clock> NodeContext<C> {
+    /// Tell the time by `clock` instead.
+    pub fn with_clock(clock: C) -> Self {
+        Self {
+            id: Default::default(),
+            all_nodes: Vec::new(),
+            metrics: Metrics::new(),
+            topology: Topology::new(),
+            topology_strategy: TopologyStrategy::default(),
+            clock,
+            initialized_at: None,
+            init_msg_id: None,
+            topology_hook: None,
+        }
+    }
+
+    /// Until (or unless) we're given a topology, connect the cluster up with
+    /// `strategy` instead of as a full mesh, e.g. [TopologyStrategy::None] to
+    /// not gossip with anybody until we're told who to.
+    pub fn with_topology_strategy(mut self, strategy: TopologyStrategy) -> Self {
+        self.topology_strategy = strategy;
+        self
+    }
+
+    /// Run every topology we accept through `hook` first, and keep
+    /// whatever it comes up with instead, e.g. our own overlay.
+    pub fn with_topology_hook(
+        mut self,
+        hook: impl FnMut(&NodeContext<C>, Topology) -> Topology + Send + 'static,
+    ) -> Self {
+        self.topology_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Take care of `envelope` if it's an `init` or a `topology` (see
+    /// [NodeContext::accept_init] and [NodeContext::accept_topology]), so
+    /// the node's own loop doesn't have to. Returns whether it was.
+    pub fn handle_setup<M: SetupMessages + Serialize>(&mut self, envelope: &Envelope<M>) -> bool {
+        if let Some((node_id, node_ids)) = envelope.message().as_init() {
+            self.accept_init(envelope, node_id, node_ids, M::init_ok());
+            return true;
+        }
+        if let Some(topology) = envelope.message().as_topology() {
+            self.accept_topology(envelope, topology, M::topology_ok());
+            return true;
+        }
+        false
+    }
+
+    /// Save what we were told in `init`. Until (or unless) we're given a
+    /// topology, our neighbors are whatever our topology strategy (see
+    /// [NodeContext::with_topology_strategy]) makes of the cluster.
+    ///
+    /// There's no grace period: that fallback is there from `init` on, so
+    /// that we're never without neighbors, and a topology that turns up
+    /// later simply replaces it.
+    pub fn init(&mut self, node_id: &NodeId, node_ids: &[NodeId]) {
+        self.id = node_id.clone();
+        self.all_nodes = node_ids.to_vec();
+        self.all_nodes.sort();
+        self.topology = Topology::with_strategy(node_ids, self.topology_strategy);
+        self.initialized_at = Some(self.clock.now());
+    }
+
+    /// Save what we were told in `init` (see [NodeContext::init]), and
+    /// acknowledge the envelope it came in with `ok`, in reply to its msg_id.
+    pub fn accept_init<M: Serialize>(
+        &mut self,
+        envelope: &Envelope<M>,
+        node_id: &NodeId,
+        node_ids: &[NodeId],
+        ok: M,
+    ) {
+        self.init(node_id, node_ids);
+        self.init_msg_id = envelope.msg_id();
+        envelope.reply(ok).send();
+    }
+
+    /// The msg_id of the `init` we got, if we got one (through
+    /// [NodeContext::accept_init]), which our `init_ok` was in reply to.
+    pub fn init_msg_id(&self) -> Option<usize> {
+        self.init_msg_id
+    }
+
+    /// How long it's been since `init`, or nothing at all if we haven't
+    /// had one yet. Handy for backoffs and leases that start when we do.
+    pub fn elapsed_since_init(&self) -> Duration {
+        self.initialized_at
+            .map(|initialized_at| self.clock.now().saturating_duration_since(initialized_at))
+            .unwrap_or_default()
+    }
+
+    /// Our own id.
+    pub fn id(&self) -> &NodeId {
+        &self.id
+    }
+
+    /// Every node in the cluster, us included, sorted by id.
+    ///
+    /// The order doesn't depend on the order `init` listed them in, so
+    /// all the nodes agree on it, and a node's position in it never changes.
+    pub fn all_nodes(&self) -> &[NodeId] {
+        &self.all_nodes
+    }
+
+    /// Save the `topology` we were given (or came up with ourselves
+    /// instead), after our topology hook (if any) has had a go at it, and
+    /// acknowledge the envelope it came in with `ok`.
+    ///
+    /// If it leaves us out (as Maelstrom has been seen to do for a
+    /// single node), we carry on without any neighbors.
+    pub fn accept_topology<M: Serialize>(
+        &mut self,
+        envelope: &Envelope<M>,
+        mut topology: Topology,
+        ok: M,
+    ) {
+        if let Some(mut hook) = self.topology_hook.take() {
+            topology = hook(self, topology);
+            self.topology_hook = Some(hook);
+        }
+        if !topology.contains(&self.id) {
+            eprintln!(
+                "We ({}) aren't in the topology, so we have no neighbors.",
+                self.id
+            );
+        }
+        self.topology = topology;
+        envelope.reply(ok).send();
+    }
+
+    /// Answer `original` with `message` right away. Handlers can call
+    /// this as often as they like, every reply gets its own msg_id, but
+    /// they're all in reply to `original`.
+    pub fn reply<M: Serialize>(&self, original: &Envelope<M>, message: M) {
+        original.reply(message).send();
+    }
+
+    /// Like [NodeContext::reply], but with a `server_ts` next to the rest
+    /// of `message`: nanoseconds since `init` (see
+    /// [NodeContext::elapsed_since_init]), which only ever go up, for working
+    /// out latencies after a run. Only for nodes that opt in, as anything
+    /// strict about the protocol won't expect the extra field.
+    pub fn reply_timestamped<M: Serialize>(&self, original: &Envelope<M>, message: M) {
+        original
+            .defer()
+            .reply(Timestamped {
+                server_ts: self.elapsed_since_init().as_nanos() as u64,
+                message,
+            })
+            .send();
+    }
+
+    /// Answer `original` with `message`, in whatever shape it expects: as
+    /// a reply if it had a msg_id (like anything a client sends), or as a
+    /// fire-and-forget message with no ids at all if it didn't (like the
+    /// gossip our peers send without waiting for an answer).
+    pub fn respond<M: Serialize>(&self, original: &Envelope<M>, message: M) {
+        match original.msg_id() {
+            Some(_) => original.reply(message).send(),
+            None => original.reply(message).without_msg_id().send(),
+        }
+    }
+
+    /// Send `message` to every other node in the cluster, and hand
+    /// back what went out, e.g. to keep track of their msg_ids.
+    pub fn broadcast_to_all<M: Serialize + Clone>(&self, message: M) -> Vec<Envelope<M>> {
+        self.all_nodes
+            .iter()
+            .filter(|&node_id| node_id != &self.id)
+            .map(|node_id| {
+                let envelope = Envelope::new(&self.id, node_id, None, message.clone());
+                envelope.send();
+                envelope
+            })
+            .collect()
+    }
+
+    pub fn topology(&self) -> &Topology {
+        &self.topology
+    }
+
+    /// Our own neighbors in the topology, i.e. whatever we accepted
+    /// last (see [NodeContext::accept_topology]).
+    pub fn neighbors(&self) -> &[NodeId] {
+        self.topology.neighbors(&self.id)
+    }
+
+    /// Time how long handling something takes, until the returned guard
+    /// is dropped. The p50/p99 of every timer go to stderr once we are.
+    pub fn timer(&self, name: &'static str) -> Timer<'_> {
+        self.metrics.timer(name)
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// The latest envelopes we received and sent, if we're recording them
+    /// (see [crate::RuntimeConfig::history_capacity]).
+    pub fn history(&self) -> History {
+        crate::history()
+    }
+
+    /// Where a node sits in [NodeContext::all_nodes].
+    pub fn position(&self, node_id: &NodeId) -> Option<usize> {
+        self.all_nodes.binary_search(node_id).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capture::capturing, drain_outbound, ManualClock};
+    use serde_json::Value;
+
+    #[test]
+    fn elapsed_since_init_follows_the_clock() {
+        let clock = ManualClock::new();
+        let mut ctx = NodeContext::with_clock(clock.clone());
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(ctx.elapsed_since_init(), Duration::ZERO, "no init yet");
+
+        ctx.init(&"n1".into(), &["n1".into()]);
+        assert_eq!(ctx.elapsed_since_init(), Duration::ZERO);
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(ctx.elapsed_since_init(), Duration::from_millis(1500));
+    }
+
+    fn nodes(count: usize) -> Vec<NodeId> {
+        (1..=count).map(|n| NodeId::from(format!("n{n}"))).collect()
+    }
+
+    #[test]
+    fn neighbors_come_from_the_strategy_without_a_topology() {
+        let mut ctx = NodeContext::new();
+        ctx.init(&"n1".into(), &nodes(4));
+        assert_eq!(ctx.neighbors(), ["n2", "n3", "n4"].map(NodeId::from));
+
+        let mut ctx = NodeContext::new().with_topology_strategy(TopologyStrategy::Ring);
+        ctx.init(&"n1".into(), &nodes(4));
+        assert_eq!(ctx.neighbors(), ["n4", "n2"].map(NodeId::from));
+
+        let mut ctx = NodeContext::new().with_topology_strategy(TopologyStrategy::None);
+        ctx.init(&"n1".into(), &nodes(4));
+        assert!(ctx.neighbors().is_empty());
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Message {
+        Init {
+            node_id: NodeId,
+            node_ids: Vec<NodeId>,
+        },
+        InitOk,
+        Topology {
+            topology: std::collections::HashMap<NodeId, Vec<NodeId>>,
+        },
+        TopologyOk,
+        Read,
+        ReadOk {
+            value: usize,
+        },
+    }
+
+    impl SetupMessages for Message {
+        fn as_init(&self) -> Option<(&NodeId, &[NodeId])> {
+            match self {
+                Message::Init { node_id, node_ids } => Some((node_id, node_ids)),
+                _ => None,
+            }
+        }
+
+        fn as_topology(&self) -> Option<Topology> {
+            match self {
+                Message::Topology { topology } => Some(Topology::from(topology.clone())),
+                _ => None,
+            }
+        }
+
+        fn init_ok() -> Self {
+            Message::InitOk
+        }
+
+        fn topology_ok() -> Self {
+            Message::TopologyOk
+        }
+    }
+
+    fn from_client(message: Message) -> Envelope<Message> {
+        Envelope::new("c1", "n1", None, message)
+    }
+
+    fn topology() -> Message {
+        Message::Topology {
+            topology: [
+                ("n1", vec!["n2"]),
+                ("n2", vec!["n1", "n3"]),
+                ("n3", vec!["n2"]),
+            ]
+            .into_iter()
+            .map(|(node, neighbors)| {
+                (
+                    node.into(),
+                    neighbors.into_iter().map(NodeId::from).collect(),
+                )
+            })
+            .collect(),
+        }
+    }
+
+    fn handle(ctx: &mut NodeContext, message: Message) -> (bool, Vec<Envelope<Value>>) {
+        let envelope = from_client(message);
+        let handled = ctx.handle_setup(&envelope);
+        (handled, drain_outbound())
+    }
+
+    #[test]
+    fn topology_is_acked_and_kept() {
+        let _capture = capturing();
+        let mut ctx = NodeContext::new().with_topology_strategy(TopologyStrategy::None);
+        let init = Message::Init {
+            node_id: "n1".into(),
+            node_ids: nodes(3),
+        };
+        let (handled, sent) = handle(&mut ctx, init);
+        assert!(handled);
+        assert_eq!(sent[0].message()["type"], "init_ok");
+        assert!(ctx.neighbors().is_empty());
+
+        let (handled, sent) = handle(&mut ctx, topology());
+        assert!(handled);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].message()["type"], "topology_ok");
+        assert_eq!(sent[0].dest, "c1");
+        assert_eq!(ctx.neighbors(), ["n2"].map(NodeId::from));
+
+        let (handled, sent) = handle(&mut ctx, Message::Read);
+        assert!(!handled && sent.is_empty());
+    }
+
+    #[test]
+    fn topology_hook_gets_the_last_word() {
+        let _capture = capturing();
+        let mut ctx = NodeContext::new().with_topology_hook(|ctx, _given| {
+            Topology::with_strategy(ctx.all_nodes(), TopologyStrategy::Ring)
+        });
+        let init = Message::Init {
+            node_id: "n1".into(),
+            node_ids: nodes(4),
+        };
+        handle(&mut ctx, init);
+        let (_, sent) = handle(&mut ctx, topology());
+        assert_eq!(sent[0].message()["type"], "topology_ok");
+        assert_eq!(ctx.neighbors(), ["n4", "n2"].map(NodeId::from));
+    }
+
+    #[test]
+    fn broadcast_to_all_skips_us() {
+        let _capture = capturing();
+        let mut ctx = NodeContext::new();
+        ctx.init(&"n2".into(), &nodes(4));
+        let returned = ctx.broadcast_to_all(Message::Read);
+
+        let sent = drain_outbound();
+        assert_eq!(sent.len(), 3);
+        let dests: Vec<_> = sent.iter().map(|envelope| envelope.dest.as_str()).collect();
+        assert_eq!(dests, ["n1", "n3", "n4"]);
+        assert!(sent.iter().all(|envelope| envelope.src == "n2"));
+        let returned: Vec<_> = returned.iter().map(|envelope| envelope.msg_id()).collect();
+        let sent: Vec<_> = sent.iter().map(|envelope| envelope.msg_id()).collect();
+        assert_eq!(returned, sent);
+    }
+
+    #[test]
+    fn init_ok_answers_the_init() {
+        let _capture = capturing();
+        let mut ctx = NodeContext::new();
+        assert_eq!(ctx.init_msg_id(), None);
+        let init = from_client(Message::Init {
+            node_id: "n1".into(),
+            node_ids: nodes(2),
+        });
+        ctx.handle_setup(&init);
+
+        let sent = drain_outbound();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].message()["type"], "init_ok");
+        assert_eq!((sent[0].src.as_str(), sent[0].dest.as_str()), ("n1", "c1"));
+        assert!(init.msg_id().is_some());
+        assert_eq!(sent[0].in_reply_to(), init.msg_id());
+        assert_eq!(ctx.init_msg_id(), init.msg_id());
+    }
+
+    #[test]
+    fn a_topology_without_us_leaves_us_without_neighbors() {
+        let _capture = capturing();
+        let mut ctx = NodeContext::new();
+        ctx.init(&"n4".into(), &nodes(4));
+        assert_eq!(ctx.neighbors().len(), 3);
+
+        // n1 to n3 only know about each other.
+        let (handled, sent) = handle(&mut ctx, topology());
+        assert!(handled);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].message()["type"], "topology_ok");
+        assert!(!ctx.topology().contains(&"n4".into()));
+        assert!(ctx.neighbors().is_empty());
+        assert_eq!(ctx.topology().neighbors(&"n2".into()).len(), 2);
+    }
+
+    #[test]
+    fn every_node_agrees_on_the_order_however_init_lists_them() {
+        let node_ids = nodes(5);
+        let contexts: Vec<NodeContext> = node_ids
+            .iter()
+            .enumerate()
+            .map(|(i, node_id)| {
+                // Every node gets the list in an order of its own.
+                let mut listed = node_ids.clone();
+                listed.rotate_left(i);
+                if i % 2 == 1 {
+                    listed.reverse();
+                }
+                let mut ctx = NodeContext::new();
+                ctx.init(node_id, &listed);
+                ctx
+            })
+            .collect();
+
+        for ctx in &contexts {
+            assert_eq!(ctx.all_nodes(), node_ids);
+            for (position, node_id) in node_ids.iter().enumerate() {
+                assert_eq!(ctx.position(node_id), Some(position));
+            }
+            assert_eq!(ctx.position(&"n9".into()), None);
+        }
+    }
+
+    #[test]
+    fn replying_twice_sends_two_replies_to_the_same_request() {
+        let _capture = capturing();
+        let mut ctx = NodeContext::new();
+        ctx.init(&"n1".into(), &nodes(2));
+        let request = from_client(Message::Read);
+        // e.g. what we've got so far, and then the rest once it's in.
+        ctx.reply(&request, Message::ReadOk { value: 1 });
+        ctx.reply(&request, Message::ReadOk { value: 2 });
+
+        let sent = drain_outbound();
+        assert_eq!(sent.len(), 2);
+        let values: Vec<_> = sent.iter().map(|reply| &reply.message()["value"]).collect();
+        assert_eq!(values, [1, 2]);
+        for reply in &sent {
+            assert_eq!((reply.src.as_str(), reply.dest.as_str()), ("n1", "c1"));
+            assert_eq!(reply.in_reply_to(), request.msg_id());
+        }
+        assert_ne!(sent[0].msg_id(), sent[1].msg_id());
+    }
+
+    #[test]
+    fn respond_replies_to_requests_with_a_msg_id() {
+        let _capture = capturing();
+        let mut ctx = NodeContext::new();
+        ctx.init(&"n1".into(), &nodes(2));
+        let request = from_client(Message::Read);
+        ctx.respond(&request, Message::ReadOk { value: 1 });
+
+        let sent = drain_outbound();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dest, "c1");
+        assert_eq!(sent[0].in_reply_to(), request.msg_id());
+        assert!(sent[0].msg_id().is_some());
+    }
+
+    #[test]
+    fn respond_leaves_the_ids_off_for_gossip() {
+        let _capture = capturing();
+        let mut ctx = NodeContext::new();
+        ctx.init(&"n1".into(), &nodes(2));
+        let gossip = Envelope::new("n2", "n1", None, Message::Read).without_msg_id();
+        ctx.respond(&gossip, Message::ReadOk { value: 1 });
+
+        let sent = drain_outbound();
+        assert_eq!(sent.len(), 1);
+        assert_eq!((sent[0].src.as_str(), sent[0].dest.as_str()), ("n1", "n2"));
+        assert_eq!(sent[0].msg_id(), None);
+        assert_eq!(sent[0].raw_in_reply_to(), crate::InReplyTo::Absent);
+        assert_eq!(sent[0].message()["value"], 1);
+    }
+
+    #[test]
+    fn timestamped_replies_carry_a_server_ts_that_only_goes_up() {
+        let _capture = capturing();
+        let clock = ManualClock::new();
+        let mut ctx = NodeContext::with_clock(clock.clone());
+        ctx.init(&"n1".into(), &nodes(2));
+        let request = from_client(Message::Read);
+        for advance in [250, 0, 1_000] {
+            clock.advance(Duration::from_micros(advance));
+            ctx.reply_timestamped(&request, Message::ReadOk { value: 1 });
+        }
+
+        let sent = drain_outbound();
+        let timestamps: Vec<u64> = sent
+            .iter()
+            .map(|reply| {
+                // Right next to the message's own fields.
+                assert_eq!(reply.message()["type"], "read_ok");
+                assert_eq!(reply.message()["value"], 1);
+                assert_eq!(reply.in_reply_to(), request.msg_id());
+                reply.message()["server_ts"].as_u64().unwrap()
+            })
+            .collect();
+        assert_eq!(timestamps, [250_000, 250_000, 1_250_000]);
+
+        // Plain replies are left alone.
+        ctx.reply(&request, Message::ReadOk { value: 1 });
+        assert!(drain_outbound()[0].message().get("server_ts").is_none());
+    }
+}
@@ -0,0 +1,163 @@
+use crate::{NodeId, Topology};
+use std::collections::{HashMap, VecDeque};
+
+/// What it takes to spread one broadcast over a [Topology].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BroadcastCost {
+    /// How many messages go between nodes, duplicates included.
+    pub messages: usize,
+    /// How many hops until the last node hears about it.
+    pub max_hops: usize,
+    /// How many nodes hear about it, the origin included.
+    pub reached: usize,
+}
+
+/// Estimates messages-per-op and latency of flooding broadcasts over a
+/// topology, without running Maelstrom, e.g. to compare overlays.
+///
+/// Assumes every link takes the same time, and that a node passes
+/// a message on (once) to every neighbor except whoever told it.
+#[derive(Debug, Clone, Copy)]
+pub struct CostModel<'a> {
+    topology: &'a Topology,
+}
+
+impl<'a> CostModel<'a> {
+    pub fn new(topology: &'a Topology) -> Self {
+        Self { topology }
+    }
+
+    /// What a single broadcast that starts at `origin` costs.
+    pub fn broadcast_from(&self, origin: &NodeId) -> BroadcastCost {
+        let mut hops: HashMap<&NodeId, usize> = HashMap::from([(origin, 0)]);
+        let mut to_visit = VecDeque::from([(origin, None)]);
+        let mut messages = 0;
+
+        while let Some((node_id, told_by)) = to_visit.pop_front() {
+            let our_hops = hops[node_id];
+            for neighbor in self.topology.neighbors(node_id) {
+                if Some(neighbor) == told_by {
+                    continue;
+                }
+                messages += 1;
+                if !hops.contains_key(neighbor) {
+                    hops.insert(neighbor, our_hops + 1);
+                    to_visit.push_back((neighbor, Some(node_id)));
+                }
+            }
+        }
+
+        BroadcastCost {
+            messages,
+            max_hops: hops.values().copied().max().unwrap_or_default(),
+            reached: hops.len(),
+        }
+    }
+
+    /// The total cost of one broadcast from each of `origins`;
+    /// hops are the worst of any single one of them.
+    pub fn broadcast_from_each(&self, origins: &[NodeId]) -> BroadcastCost {
+        origins
+            .iter()
+            .map(|origin| self.broadcast_from(origin))
+            .fold(BroadcastCost::default(), |total, cost| BroadcastCost {
+                messages: total.messages + cost.messages,
+                max_hops: total.max_hops.max(cost.max_hops),
+                reached: total.reached + cost.reached,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TopologyStrategy;
+
+    fn nodes(count: usize) -> Vec<NodeId> {
+        (1..=count).map(|n| NodeId::from(format!("n{n}"))).collect()
+    }
+
+    /// n1 at the root, n2 and n3 under it, n4 and n5 under n2,
+    /// with every link going both ways.
+    fn tree() -> Topology {
+        let mut topology = Topology::new();
+        for (node, neighbors) in [
+            ("n1", &["n2", "n3"][..]),
+            ("n2", &["n1", "n4", "n5"]),
+            ("n3", &["n1"]),
+            ("n4", &["n2"]),
+            ("n5", &["n2"]),
+        ] {
+            let neighbors = neighbors.iter().copied().map(NodeId::from).collect();
+            topology.insert(node.into(), neighbors);
+        }
+        topology
+    }
+
+    #[test]
+    fn a_tree_sends_each_node_one_message() {
+        let topology = tree();
+        let model = CostModel::new(&topology);
+        assert_eq!(
+            model.broadcast_from(&"n1".into()),
+            BroadcastCost {
+                messages: 4,
+                max_hops: 2,
+                reached: 5
+            }
+        );
+        // From a leaf, it's further to the other side.
+        assert_eq!(
+            model.broadcast_from(&"n4".into()),
+            BroadcastCost {
+                messages: 4,
+                max_hops: 3,
+                reached: 5
+            }
+        );
+        assert_eq!(
+            model.broadcast_from_each(&nodes(5)),
+            BroadcastCost {
+                messages: 20,
+                max_hops: 3,
+                reached: 25
+            }
+        );
+    }
+
+    #[test]
+    fn a_full_mesh_floods_everyone_in_one_hop() {
+        let topology = Topology::with_strategy(&nodes(4), TopologyStrategy::FullMesh);
+        let model = CostModel::new(&topology);
+        // Three from the origin, then two more from each of the other three.
+        assert_eq!(
+            model.broadcast_from(&"n1".into()),
+            BroadcastCost {
+                messages: 9,
+                max_hops: 1,
+                reached: 4
+            }
+        );
+        assert_eq!(
+            model.broadcast_from_each(&nodes(4)),
+            BroadcastCost {
+                messages: 36,
+                max_hops: 1,
+                reached: 16
+            }
+        );
+    }
+
+    #[test]
+    fn nobody_hears_about_it_without_any_links() {
+        let topology = Topology::with_strategy(&nodes(3), TopologyStrategy::None);
+        assert_eq!(
+            CostModel::new(&topology).broadcast_from(&"n2".into()),
+            BroadcastCost {
+                messages: 0,
+                max_hops: 0,
+                reached: 1
+            }
+        );
+    }
+}
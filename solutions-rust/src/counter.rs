@@ -0,0 +1,729 @@
+use crate::{Envelope, ErrorCode, NodeId, ReplyToken};
+use serde::Serialize;
+use std::{collections::HashMap, marker::PhantomData, str::FromStr};
+
+/// Coalesces adds to a counter that lives in a kv store
+/// into as few cas's as possible.
+///
+/// At most one cas is ever in flight. Anything added while
+/// we wait to hear back from the store rides along with the next one.
+#[derive(Debug, Default)]
+pub struct CasAggregator {
+    /// Guaranteed up-to-date value in the kv store
+    /// that was seen at one point in the past.
+    value: usize,
+
+    /// Adds we've accepted but haven't flushed to the store yet.
+    pending: usize,
+
+    /// The cas we're waiting on, as (msg_id, from, to).
+    in_flight: Option<(usize, usize, usize)>,
+
+    /// Whether a cas failed and we should hear the store's
+    /// latest value before trying again.
+    stale: bool,
+}
+
+impl CasAggregator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The last value we know the store had.
+    pub fn value(&self) -> usize {
+        self.value
+    }
+
+    /// Adds that haven't made it to the store yet.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Whether we're waiting to hear back about a cas.
+    pub fn is_in_flight(&self) -> bool {
+        self.in_flight.is_some()
+    }
+
+    /// Whether a cas failed and we're waiting to
+    /// hear the store's latest value before retrying.
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Buffer an add until the next flush.
+    pub fn add(&mut self, delta: usize) {
+        self.pending += delta;
+    }
+
+    /// Remember a cas (that moves the store from our
+    /// current value to `to`) as the one in flight.
+    pub fn track(&mut self, msg_id: usize, to: usize) {
+        self.in_flight = Some((msg_id, self.value, to));
+    }
+
+    /// If there's anything pending and we're free to, issue a cas
+    /// for all of it at once. `send` gets the `from` and `to` of the cas,
+    /// should send it out, and return its msg_id.
+    pub fn flush<F: FnOnce(usize, usize) -> usize>(&mut self, send: F) {
+        if self.pending == 0 || self.in_flight.is_some() || self.stale {
+            return;
+        }
+        let to = self.value + self.pending;
+        let msg_id = send(self.value, to);
+        self.track(msg_id, to);
+    }
+
+    /// The store acknowledged a cas. Returns whether it was ours.
+    pub fn cas_ok(&mut self, in_reply_to: Option<usize>) -> bool {
+        match self.in_flight {
+            Some((msg_id, from, to)) if Some(msg_id) == in_reply_to => {
+                // Only what we flushed is gone, anything
+                // added since is still pending.
+                self.pending -= to - from;
+                self.value = self.value.max(to);
+                self.in_flight = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The store rejected a cas, i.e. somebody else bumped the value
+    /// under us. Returns whether it was ours, in which case the caller
+    /// should read the latest value from the store, and we'll retry after.
+    pub fn cas_failed(&mut self, in_reply_to: Option<usize>) -> bool {
+        match self.in_flight {
+            Some((msg_id, ..)) if Some(msg_id) == in_reply_to => {
+                self.in_flight = None;
+                self.stale = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// We learnt of a value the store had at some point, e.g. from
+    /// a peer. The counter only ever grows, so the higher one wins.
+    pub fn observe(&mut self, value: usize) {
+        self.value = self.value.max(value);
+    }
+
+    /// Like [CasAggregator::observe], but straight from the store, after
+    /// a failed cas, so we're up to date enough to try again.
+    pub fn caught_up(&mut self, value: usize) {
+        self.observe(value);
+        self.stale = false;
+    }
+}
+
+/// How fresh the value we answer client reads with has to be.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CounterMode {
+    /// Answer with whatever we last saw in the kv store. Cheap, but can be stale.
+    #[default]
+    Stale,
+    /// Ask the kv store for the latest total on every client read,
+    /// and only answer once it gets back to us.
+    Linearizable,
+    /// Answer with what we last saw in the kv store, plus whatever
+    /// adds we've taken in but haven't flushed yet. Fresh from the
+    /// point of view of our own clients, but no more consistent than stale.
+    LocalOptimistic,
+}
+
+impl FromStr for CounterMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stale" => Ok(CounterMode::Stale),
+            "linearizable" => Ok(CounterMode::Linearizable),
+            "local-optimistic" => Ok(CounterMode::LocalOptimistic),
+            other => Err(format!("Unknown counter mode: {other}")),
+        }
+    }
+}
+
+/// The messages a [SeqKvCounter] has to send,
+/// built out of whatever message type the node uses.
+pub trait CounterMessages: Serialize {
+    /// A `read` of `key` in the kv store, or of a peer's
+    /// counter if there's no key.
+    fn read(key: Option<String>) -> Self;
+    fn read_ok(value: usize) -> Self;
+    fn add_ok() -> Self;
+    fn cas(key: String, from: usize, to: usize, create_if_not_exists: bool) -> Self;
+}
+
+/// Maelstrom's grow-only counter, kept in `seq-kv`.
+///
+/// Feed it the `add`s and `read`s we get (from clients and peers alike),
+/// and whatever the kv store answers with, and tick it every so often. It
+/// takes care of the rest: coalescing adds into cas's (see [CasAggregator]),
+/// catching up after a failed cas, fencing reads in [CounterMode::Linearizable],
+/// and asking our peers for their (maybe fresher) values when idle.
+#[derive(Debug)]
+pub struct SeqKvCounter<M> {
+    mode: CounterMode,
+    id: NodeId,
+    peers: Vec<NodeId>,
+    counter: CasAggregator,
+    /// Client reads that are waiting on a fresh read from the kv store,
+    /// keyed by the msg_id of that kv store read.
+    pending_reads: HashMap<usize, ReplyToken>,
+    /// Client reads whose fresh read failed, to try again on the next tick.
+    retry_reads: Vec<ReplyToken>,
+    message: PhantomData<fn() -> M>,
+}
+
+impl<M: CounterMessages> SeqKvCounter<M> {
+    /// Where the counter lives.
+    pub const KV: &'static str = "seq-kv";
+    /// Which key it's under.
+    pub const KEY: &'static str = "total";
+
+    pub fn new(mode: CounterMode) -> Self {
+        Self {
+            mode,
+            id: Default::default(),
+            peers: Vec::new(),
+            counter: CasAggregator::new(),
+            pending_reads: HashMap::new(),
+            retry_reads: Vec::new(),
+            message: PhantomData,
+        }
+    }
+
+    /// The last value we know the store had.
+    pub fn value(&self) -> usize {
+        self.counter.value()
+    }
+
+    /// Adds that haven't made it to the store yet.
+    pub fn pending(&self) -> usize {
+        self.counter.pending()
+    }
+
+    /// Learn who we are from `init`, and make sure
+    /// the counter exists in the kv store.
+    pub fn init(&mut self, node_id: &NodeId, node_ids: &[NodeId]) {
+        self.id = node_id.clone();
+        self.peers = node_ids
+            .iter()
+            .filter(|&peer| peer != node_id)
+            .cloned()
+            .collect();
+
+        // We don't flush anything else until we hear back about this one.
+        let envelope = self.to_kv(M::cas(Self::KEY.to_owned(), 0, 0, true));
+        self.counter.track(envelope.msg_id().unwrap(), 0);
+        envelope.send();
+    }
+
+    /// A client's `add`.
+    pub fn add(&mut self, envelope: &Envelope<M>, delta: usize) {
+        self.counter.add(delta);
+        envelope.reply(M::add_ok()).send();
+    }
+
+    /// A `read` of the counter, from a client or a peer.
+    pub fn read(&mut self, envelope: &Envelope<M>) {
+        let client = !envelope.is_internal();
+        match self.mode {
+            // Fence client reads behind a fresh read from the kv store.
+            CounterMode::Linearizable if client => {
+                self.fenced_read(envelope.defer());
+            }
+            // Count our own unflushed adds in, but only for clients;
+            // our peers would count them twice once we flush.
+            CounterMode::LocalOptimistic if client => {
+                let value = self.counter.value() + self.counter.pending();
+                envelope.reply(M::read_ok(value)).send();
+            }
+            _ => envelope.reply(M::read_ok(self.counter.value())).send(),
+        }
+    }
+
+    /// The kv store took our cas.
+    pub fn cas_ok(&mut self, envelope: &Envelope<M>) {
+        self.counter.cas_ok(envelope.in_reply_to());
+    }
+
+    /// A `read_ok` from a peer or the kv store. Either way, the counter
+    /// only ever grows, so a higher value than ours is more recent. Only
+    /// the kv store's gets us ready to retry a failed cas, though.
+    pub fn read_ok(&mut self, envelope: &Envelope<M>, value: usize) {
+        if envelope.src == Self::KV {
+            self.counter.caught_up(value);
+        } else {
+            self.counter.observe(value);
+        }
+        if let Some(token) = envelope
+            .in_reply_to()
+            .and_then(|msg_id| self.pending_reads.remove(&msg_id))
+        {
+            token.reply(M::read_ok(value)).send();
+        }
+    }
+
+    /// An `error` from the kv store, for a fenced read, a cas,
+    /// or the read we catch up with after a failed cas.
+    pub fn error(&mut self, envelope: &Envelope<M>, code: ErrorCode) {
+        if let Some(token) = envelope
+            .in_reply_to()
+            .and_then(|msg_id| self.pending_reads.remove(&msg_id))
+        {
+            if code == ErrorCode::KeyDoesNotExist {
+                // Nobody's added anything yet.
+                self.counter.caught_up(0);
+                token.reply(M::read_ok(0)).send();
+            } else {
+                // Try again on the next tick, rather than hammer the store.
+                self.retry_reads.push(token);
+            }
+        } else if self.counter.cas_failed(envelope.in_reply_to()) {
+            // Somebody beat us to it, so catch up before we retry.
+            self.to_kv(M::read(Some(Self::KEY.to_owned()))).send();
+        } else if code == ErrorCode::KeyDoesNotExist {
+            // The read we caught up with, before anybody added anything.
+            self.counter.caught_up(0);
+        }
+    }
+
+    /// Catch up on what we might have missed, e.g. on one
+    /// side of a partition, or because messages were slow.
+    pub fn tick(&mut self) {
+        for token in std::mem::take(&mut self.retry_reads) {
+            self.fenced_read(token);
+        }
+        if self.counter.is_stale() {
+            // We never heard back about the latest total after a failed cas.
+            self.to_kv(M::read(Some(Self::KEY.to_owned()))).send();
+        } else if self.counter.pending() == 0 {
+            for peer in &self.peers {
+                Envelope::new(&self.id, peer, None, M::read(None)).send();
+            }
+        }
+    }
+
+    /// Send whatever's pending to the kv store, if we're free to.
+    /// Call it after anything else.
+    pub fn flush(&mut self) {
+        let id = &self.id;
+        self.counter.flush(|from, to| {
+            let envelope = Envelope::new(
+                id,
+                Self::KV,
+                None,
+                M::cas(Self::KEY.to_owned(), from, to, false),
+            );
+            envelope.send();
+            envelope.msg_id().unwrap()
+        });
+    }
+
+    fn fenced_read(&mut self, token: ReplyToken) {
+        let envelope = self.to_kv(M::read(Some(Self::KEY.to_owned())));
+        self.pending_reads.insert(envelope.msg_id().unwrap(), token);
+        envelope.send();
+    }
+
+    fn to_kv(&self, message: M) -> Envelope<M> {
+        Envelope::new(&self.id, Self::KV, None, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capture::capturing, drain_outbound};
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case", tag = "type")]
+    enum Message {
+        Add {
+            delta: usize,
+        },
+        AddOk,
+        Read {
+            #[serde(skip_serializing_if = "Option::is_none")]
+            key: Option<String>,
+        },
+        ReadOk {
+            value: usize,
+        },
+        Cas {
+            key: String,
+            from: usize,
+            to: usize,
+            #[serde(default)]
+            create_if_not_exists: bool,
+        },
+        CasOk,
+        Error {
+            code: usize,
+            text: String,
+        },
+    }
+
+    impl CounterMessages for Message {
+        fn read(key: Option<String>) -> Self {
+            Message::Read { key }
+        }
+        fn read_ok(value: usize) -> Self {
+            Message::ReadOk { value }
+        }
+        fn add_ok() -> Self {
+            Message::AddOk
+        }
+        fn cas(key: String, from: usize, to: usize, create_if_not_exists: bool) -> Self {
+            Message::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            }
+        }
+    }
+
+    type Counter = SeqKvCounter<Message>;
+
+    /// Just enough of `seq-kv` for one key.
+    #[derive(Default)]
+    struct MockKv {
+        total: Option<usize>,
+    }
+
+    impl MockKv {
+        fn answer(&mut self, request: &Envelope<Message>) -> Envelope<Message> {
+            let error = |code: ErrorCode| Message::Error {
+                code: code.code(),
+                text: String::new(),
+            };
+            let reply = match (request.message(), self.total) {
+                (Message::Read { .. }, Some(value)) => Message::ReadOk { value },
+                (Message::Read { .. }, None) => error(ErrorCode::KeyDoesNotExist),
+                (
+                    Message::Cas {
+                        create_if_not_exists: true,
+                        to,
+                        ..
+                    },
+                    None,
+                ) => {
+                    self.total = Some(*to);
+                    Message::CasOk
+                }
+                (Message::Cas { .. }, None) => error(ErrorCode::KeyDoesNotExist),
+                (Message::Cas { from, to, .. }, Some(total)) if *from == total => {
+                    self.total = Some(*to);
+                    Message::CasOk
+                }
+                (Message::Cas { .. }, Some(_)) => error(ErrorCode::PreconditionFailed),
+                (other, _) => panic!("seq-kv doesn't do {other:?}"),
+            };
+            Envelope::new(Counter::KV, &request.src, request.msg_id(), reply)
+        }
+    }
+
+    fn sent() -> Vec<Envelope<Message>> {
+        drain_outbound()
+            .into_iter()
+            .map(|envelope| {
+                serde_json::from_value(serde_json::to_value(envelope).unwrap()).unwrap()
+            })
+            .collect()
+    }
+
+    fn handle(counter: &mut Counter, envelope: &Envelope<Message>) {
+        match envelope.message() {
+            Message::Add { delta } => counter.add(envelope, *delta),
+            Message::Read { .. } => counter.read(envelope),
+            Message::ReadOk { value } => counter.read_ok(envelope, *value),
+            Message::CasOk => counter.cas_ok(envelope),
+            Message::Error { code, .. } => counter.error(envelope, (*code).into()),
+            _ => {}
+        }
+    }
+
+    /// Answer everything `sent` to the store, and hand the answers to
+    /// `counter`. Whatever else was sent comes back.
+    fn answer_all(
+        counter: &mut Counter,
+        kv: &mut MockKv,
+        sent: Vec<Envelope<Message>>,
+    ) -> Vec<Envelope<Message>> {
+        let (to_kv, rest): (Vec<_>, Vec<_>) = sent
+            .into_iter()
+            .partition(|envelope| envelope.dest == Counter::KV);
+        for request in to_kv {
+            handle(counter, &kv.answer(&request));
+        }
+        rest
+    }
+
+    fn client(message: Message) -> Envelope<Message> {
+        Envelope::new("c1", "n1", None, message)
+    }
+
+    fn started(mode: CounterMode, kv: &mut MockKv) -> Counter {
+        let mut counter = Counter::new(mode);
+        counter.init(&"n1".into(), &["n1".into(), "n2".into()]);
+        answer_all(&mut counter, kv, sent());
+        counter
+    }
+
+    fn read_ok_values(sent: &[Envelope<Message>]) -> Vec<usize> {
+        sent.iter()
+            .filter_map(|envelope| match envelope.message() {
+                Message::ReadOk { value } => Some(*value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stale_read_misses_unflushed_adds() {
+        let _capture = capturing();
+        let mut kv = MockKv::default();
+        let mut counter = started(CounterMode::Stale, &mut kv);
+
+        handle(&mut counter, &client(Message::Add { delta: 5 }));
+        handle(&mut counter, &client(Message::Read { key: None }));
+        assert_eq!(read_ok_values(&sent()), [0]);
+    }
+
+    #[test]
+    fn local_optimistic_read_counts_unflushed_adds() {
+        let _capture = capturing();
+        let mut kv = MockKv::default();
+        let mut counter = started(CounterMode::LocalOptimistic, &mut kv);
+
+        handle(&mut counter, &client(Message::Add { delta: 5 }));
+        handle(&mut counter, &client(Message::Read { key: None }));
+        assert_eq!(read_ok_values(&sent()), [5]);
+    }
+
+    #[test]
+    fn linearizable_read_waits_for_the_store() {
+        let _capture = capturing();
+        let mut kv = MockKv::default();
+        let mut counter = started(CounterMode::Linearizable, &mut kv);
+        // Somebody else got there first.
+        kv.total = Some(7);
+
+        handle(&mut counter, &client(Message::Read { key: None }));
+        let fenced = sent();
+        assert!(read_ok_values(&fenced).is_empty());
+        assert_eq!(fenced.len(), 1);
+        assert_eq!(fenced[0].dest, Counter::KV);
+
+        answer_all(&mut counter, &mut kv, fenced);
+        let replies = sent();
+        assert_eq!(read_ok_values(&replies), [7]);
+        assert_eq!(replies[0].dest, "c1");
+    }
+
+    #[test]
+    fn failed_cas_catches_up_and_retries() {
+        let _capture = capturing();
+        let mut kv = MockKv::default();
+        let mut counter = started(CounterMode::Stale, &mut kv);
+
+        handle(&mut counter, &client(Message::Add { delta: 5 }));
+        counter.flush();
+        // Somebody else bumps the total while our cas is on its way.
+        kv.total = Some(10);
+        // It fails, and we read the latest total.
+        let rest = answer_all(&mut counter, &mut kv, sent());
+        assert_eq!(rest.len(), 1, "the add_ok");
+        let catch_up = sent();
+        assert!(
+            matches!(catch_up[..], [ref read] if matches!(read.message(), Message::Read { key: Some(_) }))
+        );
+        assert_eq!(counter.pending(), 5);
+
+        // No retrying until we've heard back.
+        counter.flush();
+        assert!(sent().is_empty());
+
+        answer_all(&mut counter, &mut kv, catch_up);
+        counter.flush();
+        let retry = sent();
+        assert!(matches!(
+            retry[0].message(),
+            Message::Cas {
+                from: 10,
+                to: 15,
+                ..
+            }
+        ));
+        answer_all(&mut counter, &mut kv, retry);
+        assert_eq!((counter.value(), counter.pending()), (15, 0));
+        assert_eq!(kv.total, Some(15));
+    }
+
+    #[test]
+    fn adds_during_a_cas_ride_along_with_the_next_one() {
+        let _capture = capturing();
+        let mut kv = MockKv::default();
+        let mut counter = started(CounterMode::Stale, &mut kv);
+
+        handle(&mut counter, &client(Message::Add { delta: 5 }));
+        counter.flush();
+        let in_flight: Vec<_> = sent()
+            .into_iter()
+            .filter(|envelope| envelope.dest == Counter::KV)
+            .collect();
+
+        handle(&mut counter, &client(Message::Add { delta: 3 }));
+        counter.flush();
+        assert!(sent().iter().all(|envelope| envelope.dest != Counter::KV));
+
+        answer_all(&mut counter, &mut kv, in_flight);
+        assert_eq!((counter.value(), counter.pending()), (5, 3));
+
+        counter.flush();
+        let next = sent();
+        assert!(matches!(
+            next[0].message(),
+            Message::Cas { from: 5, to: 8, .. }
+        ));
+        answer_all(&mut counter, &mut kv, next);
+        assert_eq!((counter.value(), counter.pending()), (8, 0));
+    }
+
+    #[test]
+    fn a_peers_read_ok_doesnt_count_as_catching_up() {
+        let _capture = capturing();
+        let mut kv = MockKv::default();
+        let mut counter = started(CounterMode::Stale, &mut kv);
+
+        handle(&mut counter, &client(Message::Add { delta: 5 }));
+        counter.flush();
+        kv.total = Some(10);
+        answer_all(&mut counter, &mut kv, sent());
+        // Drop the catch-up read on the floor.
+        sent();
+
+        handle(
+            &mut counter,
+            &Envelope::new("n2", "n1", None, Message::ReadOk { value: 10 }),
+        );
+        counter.flush();
+        assert!(
+            sent().is_empty(),
+            "a cas went out before the store told us anything"
+        );
+
+        // Next tick, we ask the store again.
+        counter.tick();
+        let catch_up = sent();
+        assert!(matches!(
+            catch_up[0].message(),
+            Message::Read { key: Some(_) }
+        ));
+    }
+
+    #[test]
+    fn fenced_read_of_a_missing_key_is_zero() {
+        let _capture = capturing();
+        let mut kv = MockKv::default();
+        let mut counter = Counter::new(CounterMode::Linearizable);
+        counter.init(&"n1".into(), &["n1".into()]);
+        // The create never made it.
+        sent();
+
+        handle(&mut counter, &client(Message::Read { key: None }));
+        answer_all(&mut counter, &mut kv, sent());
+        assert_eq!(read_ok_values(&sent()), [0]);
+    }
+
+    #[test]
+    fn failed_fenced_read_is_retried_on_the_next_tick() {
+        let _capture = capturing();
+        let mut kv = MockKv::default();
+        let mut counter = started(CounterMode::Linearizable, &mut kv);
+
+        handle(&mut counter, &client(Message::Read { key: None }));
+        let fenced = sent();
+        let error = Envelope::new(
+            Counter::KV,
+            "n1",
+            fenced[0].msg_id(),
+            Message::Error {
+                code: ErrorCode::Timeout.code(),
+                text: String::new(),
+            },
+        );
+        handle(&mut counter, &error);
+        assert!(sent().is_empty());
+
+        counter.tick();
+        let retried: Vec<_> = sent()
+            .into_iter()
+            .filter(|envelope| envelope.dest == Counter::KV)
+            .collect();
+        assert_eq!(retried.len(), 1);
+        answer_all(&mut counter, &mut kv, retried);
+        assert_eq!(read_ok_values(&sent()), [0]);
+    }
+
+    #[test]
+    fn two_nodes_converge_despite_conflicts() {
+        let _capture = capturing();
+        let mut kv = MockKv::default();
+        let mut nodes = [
+            Counter::new(CounterMode::Stale),
+            Counter::new(CounterMode::Stale),
+        ];
+        for (counter, id) in nodes.iter_mut().zip(["n1", "n2"]) {
+            counter.init(&id.into(), &["n1".into(), "n2".into()]);
+        }
+
+        let mut in_flight = sent();
+        let mut total = 0;
+        for round in 1..=20 {
+            for (node, counter) in nodes.iter_mut().enumerate() {
+                let delta = round + node;
+                total += delta;
+                handle(
+                    counter,
+                    &Envelope::new("c1", counter.id.as_str(), None, Message::Add { delta }),
+                );
+                counter.flush();
+            }
+            in_flight.extend(sent());
+            // Both nodes' cas's are out at once, so one of them loses.
+            for envelope in std::mem::take(&mut in_flight) {
+                let node = if envelope.src == "n1" { 0 } else { 1 };
+                if envelope.dest == Counter::KV {
+                    handle(&mut nodes[node], &kv.answer(&envelope));
+                }
+            }
+            in_flight.extend(sent());
+        }
+
+        // Let things settle.
+        for _ in 0..10 {
+            for counter in nodes.iter_mut() {
+                counter.tick();
+                counter.flush();
+            }
+            for envelope in sent().into_iter().chain(std::mem::take(&mut in_flight)) {
+                let node = if envelope.src == "n1" { 0 } else { 1 };
+                if envelope.dest == Counter::KV {
+                    handle(&mut nodes[node], &kv.answer(&envelope));
+                } else if envelope.src.is_node() && envelope.dest.is_node() {
+                    handle(&mut nodes[1 - node], &envelope);
+                }
+            }
+        }
+
+        assert_eq!(kv.total, Some(total));
+        for counter in &nodes {
+            assert_eq!((counter.value(), counter.pending()), (total, 0));
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{Envelope, NodeId};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashSet},
+    hash::Hash,
+};
+
+/// State that any two replicas can merge, in any order, any number of
+/// times, and still end up agreeing, e.g. a grow-only set or a G-counter.
+pub trait Crdt {
+    /// Fold `other` into us. Returns whether that changed anything.
+    fn merge(&mut self, other: &Self) -> bool;
+}
+
+/// A grow-only set.
+impl<T: Eq + Hash + Clone> Crdt for HashSet<T> {
+    fn merge(&mut self, other: &Self) -> bool {
+        let before = self.len();
+        self.extend(other.iter().cloned());
+        self.len() != before
+    }
+}
+
+/// A grow-only set.
+impl<T: Ord + Clone> Crdt for BTreeSet<T> {
+    fn merge(&mut self, other: &Self) -> bool {
+        let before = self.len();
+        self.extend(other.iter().cloned());
+        self.len() != before
+    }
+}
+
+/// Keeps a [Crdt] in sync across the cluster by gossiping the whole thing.
+///
+/// Every tick, we send our full state to our neighbors. Whatever they
+/// send us gets merged in, so a lost message is made up for by the next
+/// one. If a merge changed anything, it's worth gossiping again right
+/// away (e.g. with [crate::Events::request_flush]).
+#[derive(Debug, Clone, Default)]
+pub struct StateBased<T> {
+    state: T,
+}
+
+impl<T: Crdt + Clone> StateBased<T> {
+    pub fn new(state: T) -> Self {
+        Self { state }
+    }
+
+    pub fn state(&self) -> &T {
+        &self.state
+    }
+
+    /// Make a local change, e.g. add an element.
+    pub fn update<F: FnOnce(&mut T)>(&mut self, update: F) {
+        update(&mut self.state);
+    }
+
+    /// Merge in a peer's state. Returns whether that changed ours.
+    pub fn receive(&mut self, remote: &T) -> bool {
+        self.state.merge(remote)
+    }
+
+    /// Send our whole state to each of `neighbors`,
+    /// wrapped into whatever message `wrap` makes of it.
+    pub fn gossip<M, F>(&self, src: &NodeId, neighbors: &[NodeId], wrap: F)
+    where
+        M: Serialize,
+        F: Fn(T) -> M,
+    {
+        for neighbor in neighbors {
+            Envelope::new(src, neighbor, None, wrap(self.state.clone())).send();
+        }
+    }
+}
+
+/// A value that only ever goes up, e.g. a total we've read from somewhere.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Max<T>(pub T);
+
+impl<T: Ord + Clone> Crdt for Max<T> {
+    fn merge(&mut self, other: &Self) -> bool {
+        if other.0 > self.0 {
+            self.0 = other.0.clone();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Catches us up whenever a peer turns out to know more than we do
+/// (e.g. its `read_ok` had a higher value than ours), and remembers that
+/// we owe our neighbors a gossip, so they catch up too.
+#[derive(Debug, Clone, Default)]
+pub struct ReadRepair<T> {
+    state: T,
+    gossip_due: bool,
+}
+
+impl<T: Crdt> ReadRepair<T> {
+    pub fn new(state: T) -> Self {
+        Self {
+            state,
+            gossip_due: false,
+        }
+    }
+
+    pub fn state(&self) -> &T {
+        &self.state
+    }
+
+    /// Merge in what a peer told us. Returns whether we were behind.
+    pub fn repair(&mut self, remote: &T) -> bool {
+        let repaired = self.state.merge(remote);
+        self.gossip_due |= repaired;
+        repaired
+    }
+
+    /// Whether a repair changed anything since we last asked, e.g. to
+    /// [crate::Events::request_flush] if it did.
+    pub fn take_gossip_due(&mut self) -> bool {
+        std::mem::take(&mut self.gossip_due)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capture::capturing, drain_outbound, lossy, Flow};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Message {
+        Gossip { state: BTreeSet<usize> },
+    }
+
+    #[test]
+    fn three_replicas_converge_despite_losing_messages() {
+        let _capture = capturing();
+        let ids: Vec<NodeId> = ["n1", "n2", "n3"].map(NodeId::from).to_vec();
+        let mut replicas: Vec<StateBased<BTreeSet<usize>>> =
+            (0..3).map(|_| StateBased::default()).collect();
+        for (index, replica) in replicas.iter_mut().enumerate() {
+            replica.update(|state| state.extend([index * 10, index * 10 + 1]));
+        }
+
+        // Lose two gossips out of every five.
+        let mut lose = lossy(0.4, 7);
+        for _round in 0..10 {
+            for (id, replica) in ids.iter().zip(&replicas) {
+                let neighbors: Vec<NodeId> =
+                    ids.iter().filter(|&other| other != id).cloned().collect();
+                replica.gossip(id, &neighbors, |state| Message::Gossip { state });
+            }
+            for envelope in drain_outbound() {
+                let envelope: Envelope<Message> =
+                    serde_json::from_value(serde_json::to_value(envelope).unwrap()).unwrap();
+                if lose(&envelope) == Flow::Drop {
+                    continue;
+                }
+                let Message::Gossip { state } = envelope.message();
+                let to = ids
+                    .iter()
+                    .position(|id| envelope.dest == id.as_str())
+                    .unwrap();
+                replicas[to].receive(state);
+            }
+        }
+
+        let everything = BTreeSet::from([0, 1, 10, 11, 20, 21]);
+        for replica in &replicas {
+            assert_eq!(replica.state(), &everything);
+        }
+    }
+
+    #[test]
+    fn merging_is_idempotent_and_says_when_it_changed_something() {
+        let mut ours = HashSet::from([1, 2]);
+        assert!(ours.merge(&HashSet::from([2, 3])));
+        assert!(!ours.merge(&HashSet::from([2, 3])));
+        assert_eq!(ours, HashSet::from([1, 2, 3]));
+
+        let mut max = Max(5);
+        assert!(!max.merge(&Max(3)));
+        assert!(max.merge(&Max(8)));
+        assert_eq!(max, Max(8));
+    }
+
+    #[test]
+    fn read_repair_owes_a_gossip_only_when_behind() {
+        let mut total = ReadRepair::new(Max(10));
+        assert!(!total.repair(&Max(7)));
+        assert!(!total.take_gossip_due());
+        assert!(total.repair(&Max(12)));
+        assert!(total.take_gossip_due());
+        assert!(!total.take_gossip_due());
+        assert_eq!(total.state(), &Max(12));
+    }
+}
@@ -0,0 +1,210 @@
+use crate::{Envelope, NodeId};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// A set of broadcast messages that's mirrored to a kv store (e.g. `seq-kv`),
+/// so that a node that gets restarted can pick up where it left off.
+///
+/// The whole set lives under a key of its own for every node, so nobody else
+/// ever writes to it, and a plain `write` of everything is all it takes. Inserts
+/// only mark the set as changed; it gets written out (at most once per call)
+/// by [DurableSet::flush], e.g. on every tick, instead of once per insert.
+#[derive(Debug, Clone, Default)]
+pub struct DurableSet {
+    key: String,
+    items: BTreeSet<usize>,
+    /// Whether we've got anything the kv store hasn't.
+    dirty: bool,
+    /// The msg_id of our read, while we're waiting to hear back.
+    loading: Option<usize>,
+}
+
+impl DurableSet {
+    /// The set for the node `node_id`, empty until it's loaded.
+    pub fn new(node_id: &NodeId) -> Self {
+        Self {
+            key: format!("{node_id}/messages"),
+            ..Default::default()
+        }
+    }
+
+    /// Where in the kv store we keep our items.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns whether `item` is new.
+    pub fn insert(&mut self, item: usize) -> bool {
+        let inserted = self.items.insert(item);
+        self.dirty |= inserted;
+        inserted
+    }
+
+    pub fn contains(&self, item: usize) -> bool {
+        self.items.contains(&item)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.items.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Whether we're still waiting on what we saved before a restart.
+    pub fn is_loading(&self) -> bool {
+        self.loading.is_some()
+    }
+
+    /// Ask `kv` for whatever we saved before, with the read `read` makes
+    /// of our key. Call it once `init` tells us who we are.
+    pub fn load<M, F>(&mut self, src: &NodeId, kv: &str, read: F)
+    where
+        M: Serialize,
+        F: FnOnce(String) -> M,
+    {
+        let envelope = Envelope::new(src, kv, None, read(self.key.clone()));
+        self.loading = envelope.msg_id();
+        envelope.send();
+    }
+
+    /// Merge in what our read (`in_reply_to`) came back with, which is
+    /// nothing at all if the key didn't exist yet. Anything inserted in the
+    /// meantime is kept. Returns whether this was the answer to our read.
+    pub fn loaded(&mut self, in_reply_to: Option<usize>, items: &[usize]) -> bool {
+        if in_reply_to.is_none() || in_reply_to != self.loading {
+            return false;
+        }
+        self.loading = None;
+        let saved: BTreeSet<usize> = items.iter().copied().collect();
+        self.dirty |= !self.items.is_subset(&saved);
+        self.items.extend(saved);
+        true
+    }
+
+    /// Write the whole set to `kv` (with the write `write` makes of our key
+    /// and items), if it changed since we last did. Nothing gets written until
+    /// we're done loading, so we never overwrite what we haven't read yet.
+    pub fn flush<M, F>(&mut self, src: &NodeId, kv: &str, write: F)
+    where
+        M: Serialize,
+        F: FnOnce(String, Vec<usize>) -> M,
+    {
+        if !self.dirty || self.is_loading() {
+            return;
+        }
+        self.dirty = false;
+        Envelope::new(
+            src,
+            kv,
+            None,
+            write(self.key.clone(), self.iter().collect()),
+        )
+        .send();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capture::capturing, drain_outbound};
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    const KV: &str = "seq-kv";
+
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Message {
+        Read { key: String },
+        Write { key: String, value: Vec<usize> },
+    }
+
+    /// Just enough of `seq-kv` for us: it takes in what we wrote, and
+    /// hands back the msg_id of our read along with what it found.
+    #[derive(Default)]
+    struct Kv(HashMap<String, Vec<usize>>);
+
+    impl Kv {
+        fn handle_sent(&mut self) -> Option<(Option<usize>, Vec<usize>)> {
+            let mut answer = None;
+            for envelope in drain_outbound() {
+                let msg_id = envelope.msg_id();
+                let envelope: Envelope<Message> =
+                    serde_json::from_value(serde_json::to_value(envelope).unwrap()).unwrap();
+                match envelope.message() {
+                    Message::Write { key, value } => {
+                        self.0.insert(key.clone(), value.clone());
+                    }
+                    Message::Read { key } => {
+                        answer = Some((msg_id, self.0.get(key).cloned().unwrap_or_default()));
+                    }
+                }
+            }
+            answer
+        }
+    }
+
+    fn flush(set: &mut DurableSet, node: &NodeId) {
+        set.flush(node, KV, |key, value| Message::Write { key, value });
+    }
+
+    /// Start `node` up from whatever `kv` has for it.
+    fn restart(node: &NodeId, kv: &mut Kv) -> DurableSet {
+        let mut set = DurableSet::new(node);
+        set.load(node, KV, |key| Message::Read { key });
+        assert!(set.is_loading());
+        let (in_reply_to, items) = kv.handle_sent().unwrap();
+        // Only the answer to our own read counts.
+        assert!(!set.loaded(Some(usize::MAX), &[99]));
+        assert!(set.loaded(in_reply_to, &items));
+        assert!(!set.is_loading());
+        set
+    }
+
+    #[test]
+    fn a_restarted_node_gets_its_set_back() {
+        let _capture = capturing();
+        let (node, mut kv) = (NodeId::from("n1"), Kv::default());
+        let mut set = restart(&node, &mut kv);
+        assert!(set.is_empty(), "nothing saved yet");
+
+        for item in [3, 1, 2] {
+            set.insert(item);
+        }
+        flush(&mut set, &node);
+        kv.handle_sent();
+        // Nothing changed, so nothing to write.
+        flush(&mut set, &node);
+        assert!(drain_outbound().is_empty());
+
+        let set = restart(&node, &mut kv);
+        assert_eq!(set.iter().collect::<Vec<_>>(), [1, 2, 3]);
+        // Someone else's set is their own.
+        assert!(restart(&NodeId::from("n2"), &mut kv).is_empty());
+    }
+
+    #[test]
+    fn a_partial_save_is_topped_up() {
+        let _capture = capturing();
+        let (node, mut kv) = (NodeId::from("n1"), Kv::default());
+        let mut set = DurableSet::new(&node);
+        // Whatever we insert before hearing back is kept, but not written.
+        set.load(&node, KV, |key| Message::Read { key });
+        set.insert(4);
+        flush(&mut set, &node);
+        kv.0.insert(set.key().to_owned(), vec![1, 2]);
+        let (in_reply_to, items) = kv.handle_sent().unwrap();
+        assert!(set.loaded(in_reply_to, &items));
+        assert_eq!(set.iter().collect::<Vec<_>>(), [1, 2, 4]);
+
+        flush(&mut set, &node);
+        kv.handle_sent();
+        assert_eq!(kv.0[set.key()], [1, 2, 4]);
+    }
+}
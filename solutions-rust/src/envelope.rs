@@ -1,5 +1,8 @@
-use serde::{Deserialize, Serialize};
+use crate::{Address, Framer, MalformedRequest, NoMsgId};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
 use std::{
+    collections::VecDeque,
     io::Write,
     ops::Deref,
     sync::atomic::{AtomicUsize, Ordering},
@@ -9,6 +12,12 @@ use std::{
 /// and replies if it is a communication between
 /// the client and the server. For messages between
 /// servers, msg_id's and in_reply_to's are optional.
+///
+/// Message types never carry their own msg_id or in_reply_to, they
+/// live here, so every message decodes the same way whether they're
+/// there, missing, or `null` (the last two both being `None`), and only
+/// go back out on the wire when they're there. The one exception is an
+/// explicit `"in_reply_to": null`, which is kept as such (see [InReplyTo]).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Body<M> {
     /// The id that the client gives us for any rpc it makes.
@@ -16,12 +25,74 @@ pub struct Body<M> {
     msg_id: Option<usize>,
 
     /// The message our rpc response corresponds to.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    in_reply_to: Option<usize>,
+    #[serde(default, skip_serializing_if = "InReplyTo::is_absent")]
+    in_reply_to: InReplyTo,
+
+    /// Whether this went between two of our nodes, as opposed to
+    /// between a node and a client or a service (e.g. `seq-kv`).
+    #[serde(default, skip_serializing_if = "is_false")]
+    internal: bool,
 
     /// The actual payload.
     #[serde(flatten)]
     message: M,
+
+    /// Every field the body came in with besides the ids, as it was,
+    /// including any our message type doesn't know about.
+    #[serde(flatten, skip_serializing)]
+    extra: Map<String, Value>,
+
+    /// Fields to send along with the message, e.g. echoed
+    /// back from a request (see [Envelope::reply_echoing]).
+    #[serde(flatten, skip_deserializing)]
+    echoed: Map<String, Value>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// A body's `in_reply_to`, telling apart a field that's missing from
+/// one that's explicitly `null`, for when being strict about the protocol.
+/// Either way round, it goes back out just like it came in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InReplyTo {
+    Present(usize),
+    Null,
+    #[default]
+    Absent,
+}
+
+impl InReplyTo {
+    pub fn get(&self) -> Option<usize> {
+        match self {
+            InReplyTo::Present(msg_id) => Some(*msg_id),
+            InReplyTo::Null | InReplyTo::Absent => None,
+        }
+    }
+
+    pub fn is_absent(&self) -> bool {
+        *self == InReplyTo::Absent
+    }
+}
+
+impl From<Option<usize>> for InReplyTo {
+    fn from(in_reply_to: Option<usize>) -> Self {
+        in_reply_to.map_or(InReplyTo::Absent, InReplyTo::Present)
+    }
+}
+
+impl Serialize for InReplyTo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.get().serialize(serializer)
+    }
+}
+
+/// Only ever called when the field is there, otherwise it's the default.
+impl<'de> Deserialize<'de> for InReplyTo {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Option::<usize>::deserialize(deserializer)?.map_or(InReplyTo::Null, InReplyTo::Present))
+    }
 }
 
 impl<M> Body<M> {
@@ -29,20 +100,58 @@ impl<M> Body<M> {
         self.msg_id
     }
     pub fn in_reply_to(&self) -> Option<usize> {
+        self.in_reply_to.get()
+    }
+
+    /// Like [Body::in_reply_to], but telling a `null` from a missing field.
+    pub fn raw_in_reply_to(&self) -> InReplyTo {
         self.in_reply_to
     }
     pub fn message(&self) -> &M {
         &self.message
     }
+    pub fn internal(&self) -> bool {
+        self.internal
+    }
+
+    /// The fields this body came in with (besides the ids), whether or
+    /// not our message type knows about them. Empty for ones we made.
+    pub fn extra(&self) -> &Map<String, Value> {
+        &self.extra
+    }
+
+    /// The same body, but identified by the given msg_id.
+    pub fn with_msg_id(self, msg_id: usize) -> Self {
+        Self {
+            msg_id: Some(msg_id),
+            ..self
+        }
+    }
+
+    /// The same body, but without a msg_id.
+    pub fn without_msg_id(self) -> Self {
+        Self {
+            msg_id: None,
+            ..self
+        }
+    }
+
+    /// The same body, but not a reply to anything.
+    pub fn without_in_reply_to(self) -> Self {
+        Self {
+            in_reply_to: InReplyTo::Absent,
+            ..self
+        }
+    }
 }
 
 static MESSAGE_ID: AtomicUsize = AtomicUsize::new(0);
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Envelope<M> {
-    pub src: String,
-    pub dest: String,
-    body: Body<M>,
+    pub src: Address,
+    pub dest: Address,
+    pub(crate) body: Body<M>,
 }
 
 impl<M> Envelope<M>
@@ -53,20 +162,25 @@ where
     /// (optionally) as a reply to another message, that contains a given body.
     pub fn new(src: &str, dest: &str, in_reply_to: Option<usize>, message: M) -> Envelope<M> {
         Self {
-            src: src.to_owned(),
-            dest: dest.to_owned(),
+            src: src.into(),
+            dest: dest.into(),
             body: Body {
                 msg_id: Some(MESSAGE_ID.fetch_add(1, Ordering::SeqCst)),
-                in_reply_to,
+                in_reply_to: in_reply_to.into(),
+                internal: false,
                 message,
+                extra: Map::new(),
+                echoed: Map::new(),
             },
         }
+        .with_internal_marker()
     }
 
     /// Returns whether this envelope has messages meant
-    /// for inter-server communication.
+    /// for inter-server communication, either because the
+    /// sender flagged it so, or because it came from a node.
     pub fn is_internal(&self) -> bool {
-        self.src.starts_with('n')
+        self.body.internal || self.src.is_node()
     }
 
     /// Generate a reply for us envelope that contains
@@ -77,18 +191,331 @@ where
             dest: self.src.clone(),
             body: Body {
                 msg_id: Some(MESSAGE_ID.fetch_add(1, Ordering::SeqCst)),
-                in_reply_to: self.msg_id(),
+                in_reply_to: self.msg_id().into(),
+                internal: false,
                 message,
+                extra: Map::new(),
+                echoed: Map::new(),
             },
         }
+        .with_internal_marker()
+    }
+
+    /// Like [Envelope::reply], but only if we can tell which message the
+    /// reply is for, i.e. this envelope had a msg_id, as anything a
+    /// client sends does.
+    pub fn try_reply(&self, message: M) -> Result<Envelope<M>, NoMsgId> {
+        match self.msg_id() {
+            Some(_) => Ok(self.reply(message)),
+            None => Err(NoMsgId {
+                src: self.src.clone(),
+            }),
+        }
+    }
+
+    /// Like [Envelope::reply], but the named fields of our request get
+    /// copied over into the reply too (unless it already has them), for
+    /// workloads that expect some of what they sent echoed back. Fields
+    /// our message type doesn't know about can be echoed too (see [Body::extra]).
+    pub fn reply_echoing<R: Serialize>(&self, fields: &[&str], message: R) -> Envelope<R> {
+        let own = serde_json::to_value(&message).unwrap();
+        let mut reply = Envelope::new(&self.dest, &self.src, self.msg_id(), message);
+        for field in fields {
+            if let (None, Some(value)) = (own.get(*field), self.body.extra.get(*field)) {
+                reply.body.echoed.insert(field.to_string(), value.clone());
+            }
+        }
+        reply
     }
 
-    /// Send messages out to stdout.
+    /// Generate a reply on behalf of somebody else, e.g. when we're
+    /// a proxy relaying a backend's answer (this envelope) to the client
+    /// whose request we forwarded (the token).
+    pub fn reply_to(&self, original: &ReplyToken, message: M) -> Envelope<M> {
+        Envelope::new(&self.dest, &original.src, original.msg_id, message)
+    }
+
+    /// Pass the body of this envelope on to somebody else, as a fresh
+    /// message from us that isn't a reply to anything.
+    pub fn forward_body(&self, dest: &str) -> Envelope<M>
+    where
+        M: Clone,
+    {
+        Envelope {
+            src: self.dest.clone(),
+            dest: dest.into(),
+            body: self
+                .body
+                .clone()
+                .without_in_reply_to()
+                .with_msg_id(MESSAGE_ID.fetch_add(1, Ordering::SeqCst)),
+        }
+        .with_internal_marker()
+    }
+
+    /// The same envelope, but without a msg_id, i.e. not expecting a reply.
+    pub(crate) fn without_msg_id(mut self) -> Self {
+        self.body = self.body.without_msg_id();
+        self
+    }
+
+    /// Flag the body as internal if (and only if) it goes between two nodes.
+    fn with_internal_marker(mut self) -> Self {
+        self.body.internal = self.src.is_node() && self.dest.is_node();
+        self
+    }
+
+    /// Remember who to answer for this envelope, so that
+    /// the reply can be sent later, after we've heard
+    /// back from somebody else (e.g. a kv store).
+    pub fn defer(&self) -> ReplyToken {
+        ReplyToken {
+            src: self.src.clone(),
+            dest: self.dest.clone(),
+            msg_id: self.msg_id(),
+        }
+    }
+
+    /// Acknowledge this envelope with `receipt` right away, and hand back
+    /// a token to send the real answer with later (see [ReplyToken::complete]).
+    pub fn ack_now_reply_later(&self, receipt: M) -> ReplyToken {
+        self.reply(receipt).send();
+        self.defer()
+    }
+
+    /// What the envelope should look like in the logs.
+    pub fn log_repr(&self, verbosity: Verbosity) -> String {
+        match verbosity {
+            Verbosity::Low => serde_json::to_string(self),
+            Verbosity::High => serde_json::to_string_pretty(self),
+        }
+        .unwrap()
+    }
+
+    /// How many bytes this envelope takes up as json (not counting the
+    /// line ending), without actually holding on to the json.
+    pub fn size_bytes(&self) -> usize {
+        let mut counter = ByteCounter(0);
+        serde_json::to_writer(&mut counter, self).unwrap();
+        counter.0
+    }
+
+    /// Send messages out to stdout (or to [crate::drain_outbound], if
+    /// we are capturing them, or along with the rest of a [crate::batched]).
+    ///
+    /// Safe to call from any thread, e.g. workers replying to requests
+    /// handed to them by the main loop: the whole line gets written while
+    /// we hold the lock on stdout, so lines from different threads never
+    /// end up interleaved, and msg_ids come from a single atomic counter,
+    /// so they never collide either.
     pub fn send(&self) {
-        let mut stdout = std::io::stdout().lock();
-        serde_json::to_writer(&mut stdout, self).unwrap();
-        stdout.write_all(b"\n").unwrap();
-        stdout.flush().unwrap();
+        send_line(serde_json::to_vec(self).unwrap());
+    }
+}
+
+/// Write out an already serialized envelope, the same way [Envelope::send]
+/// would, framed however stdout is (see [crate::RuntimeConfig::framing]).
+pub(crate) fn send_line(line: Vec<u8>) {
+    crate::history::record_line(crate::Direction::Sent, &line);
+    crate::tap::record_line(crate::Direction::Sent, &line);
+
+    let mut frame = Vec::with_capacity(line.len() + 4);
+    crate::config::framing()
+        .write_frame(&mut frame, &line)
+        .unwrap();
+    if !crate::batch::buffer_line(&frame) {
+        write_out(&frame).unwrap();
+    }
+
+    #[cfg(feature = "stats")]
+    crate::Statistics::global().record_outbound(frame.len());
+}
+
+/// Hand whole frames over to wherever they're going: nowhere on a dry run,
+/// the capture buffer (standing in for stdout) if we're capturing, and
+/// stdout otherwise.
+pub(crate) fn write_out(lines: &[u8]) -> std::io::Result<()> {
+    if crate::config::is_dry_run() || crate::capture::capture_lines(lines) {
+        return Ok(());
+    }
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(lines)?;
+    stdout.flush()
+}
+
+/// Pack `items` into as few envelopes as we can (built with `build`), so
+/// that none of them serializes to more than `max_bytes`, e.g. to break
+/// up a huge `sync` into a few smaller ones. Items go out in order, and an
+/// item too big to fit anywhere gets an envelope of its own.
+///
+/// Sizes are first estimated as if the items sat in a json array, which is
+/// what a `Vec` field turns into, and then checked against what `build`
+/// actually made, e.g. in case its msg_id had a few more digits.
+pub fn split_by_size<T, M, F>(items: &[T], max_bytes: usize, mut build: F) -> Vec<Envelope<M>>
+where
+    T: Serialize + Clone,
+    M: Serialize,
+    F: FnMut(Vec<T>) -> Envelope<M>,
+{
+    if items.is_empty() {
+        return Vec::new();
+    }
+
+    let empty = build(Vec::new()).size_bytes();
+    let item_size = |item: &T| {
+        let mut counter = ByteCounter(0);
+        serde_json::to_writer(&mut counter, item).unwrap();
+        counter.0
+    };
+
+    let mut queue: VecDeque<T> = items.iter().cloned().collect();
+    let mut envelopes = Vec::new();
+    while !queue.is_empty() {
+        let mut chunk = Vec::new();
+        let mut size = empty;
+        while let Some(item) = queue.front() {
+            // Every item after the first costs a comma too.
+            let extra = item_size(item) + usize::from(!chunk.is_empty());
+            if !chunk.is_empty() && size + extra > max_bytes {
+                break;
+            }
+            size += extra;
+            chunk.extend(queue.pop_front());
+        }
+
+        loop {
+            let envelope = build(chunk.clone());
+            if chunk.len() == 1 || envelope.size_bytes() <= max_bytes {
+                envelopes.push(envelope);
+                break;
+            }
+            queue.push_front(chunk.pop().unwrap());
+        }
+    }
+    envelopes
+}
+
+/// Throws away whatever gets written to it, but keeps count.
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How much detail to put in the logs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Compact, one line per envelope.
+    #[default]
+    Low,
+    /// Pretty-printed json.
+    High,
+}
+
+/// How picky to be about inbound bodies.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strictness {
+    /// Ignore any fields the message type doesn't know about.
+    #[default]
+    Lenient,
+    /// Reject bodies that carry fields the message type
+    /// doesn't know about, like `deny_unknown_fields` would.
+    Strict,
+}
+
+impl<M> Envelope<M>
+where
+    M: Serialize + DeserializeOwned,
+{
+    /// Decode an envelope from a line of json, reporting
+    /// anything we can't make sense of as a [MalformedRequest].
+    pub fn parse(line: &str, strictness: Strictness) -> Result<Envelope<M>, MalformedRequest> {
+        let raw: Value = serde_json::from_str(line).map_err(|err| MalformedRequest {
+            token: None,
+            reason: err.to_string(),
+        })?;
+
+        let malformed = |reason: String| MalformedRequest {
+            token: Some(ReplyToken {
+                src: raw["src"].as_str().unwrap_or_default().into(),
+                dest: raw["dest"].as_str().unwrap_or_default().into(),
+                msg_id: raw["body"]["msg_id"].as_u64().map(|msg_id| msg_id as usize),
+            })
+            .filter(|token| !token.src.is_empty()),
+            reason,
+        };
+
+        let envelope: Envelope<M> =
+            serde_json::from_value(raw.clone()).map_err(|err| malformed(err.to_string()))?;
+
+        if strictness == Strictness::Strict {
+            if let Some(field) = raw["body"]
+                .as_object()
+                .and_then(|body| unknown_field(&envelope.body, body))
+            {
+                return Err(malformed(format!("unknown field `{field}`")));
+            }
+        }
+
+        Ok(envelope)
+    }
+}
+
+/// The first field of `raw` (other than a `null`) that decoding it into `body` didn't use.
+///
+/// Anything the message type decoded normally shows up again when we
+/// serialize it, so only the fields that don't get a closer look: swap
+/// each for a value no real field would take as is, and if the body still
+/// decodes, and comes out the same, nothing used it. That way, e.g. an
+/// empty `Vec` skipped on the way out still counts as known.
+fn unknown_field<M>(body: &Body<M>, raw: &Map<String, Value>) -> Option<String>
+where
+    M: Serialize + DeserializeOwned,
+{
+    let known = serde_json::to_value(body).unwrap();
+    raw.iter()
+        .filter(|(field, _)| known.get(field.as_str()).is_none())
+        .map(|(field, _)| field)
+        .find(|field| {
+            let mut probe = raw.clone();
+            probe.insert(
+                field.to_string(),
+                serde_json::json!({ "\u{0}probe": [null] }),
+            );
+            serde_json::from_value::<Body<M>>(Value::Object(probe))
+                .is_ok_and(|probed| serde_json::to_value(probed).unwrap() == known)
+        })
+        .cloned()
+}
+
+/// A handle on a request that we haven't replied to yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplyToken {
+    /// Whoever sent us the request.
+    pub src: Address,
+    /// Us.
+    pub dest: Address,
+    /// The id of the request, if it had one.
+    pub msg_id: Option<usize>,
+}
+
+impl ReplyToken {
+    /// Generate the reply for the deferred request
+    /// that contains the specified body.
+    pub fn reply<M: Serialize>(&self, message: M) -> Envelope<M> {
+        Envelope::new(&self.dest, &self.src, self.msg_id, message)
+    }
+
+    /// Send the reply for the deferred request.
+    pub fn complete<M: Serialize>(&self, message: M) {
+        self.reply(message).send();
     }
 }
 
@@ -101,3 +528,379 @@ impl<M> Deref for Envelope<M> {
         &self.body
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorCode;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Message {
+        Read {
+            key: usize,
+        },
+        ReadOk {
+            value: usize,
+        },
+        Sync {
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            messages: Vec<usize>,
+        },
+    }
+
+    #[test]
+    fn size_bytes_matches_the_json() {
+        let envelopes = [
+            Envelope::new("c1", "n1", None, Message::Read { key: 1 }),
+            Envelope::new(
+                "n1",
+                "n2",
+                Some(123456),
+                Message::ReadOk { value: usize::MAX },
+            ),
+            Envelope::new("n1", "c1", None, Message::Read { key: 1 }).without_msg_id(),
+        ];
+        for envelope in envelopes {
+            assert_eq!(
+                envelope.size_bytes(),
+                serde_json::to_string(&envelope).unwrap().len()
+            );
+        }
+    }
+
+    #[test]
+    fn split_chunks_fit_and_add_back_up() {
+        #[derive(Debug, Serialize)]
+        #[serde(tag = "type", rename_all = "snake_case")]
+        enum Sync {
+            Sync { messages: Vec<usize> },
+        }
+
+        let items: Vec<usize> = (0..1000).map(|item| item * 37).collect();
+        let envelopes = split_by_size(&items, 300, |messages| {
+            Envelope::new("n1", "n2", None, Sync::Sync { messages })
+        });
+        assert!(envelopes.len() > 1);
+        let mut together: Vec<usize> = Vec::new();
+        for envelope in &envelopes {
+            assert!(envelope.size_bytes() <= 300, "{}", envelope.size_bytes());
+            let Sync::Sync { messages } = envelope.message();
+            together.extend(messages);
+        }
+        assert_eq!(together, items);
+    }
+
+    #[test]
+    fn split_gives_big_items_an_envelope_of_their_own() {
+        #[derive(Debug, Serialize)]
+        struct Batch {
+            items: Vec<String>,
+        }
+
+        let batch = |items| Envelope::new("n1", "n2", None, Batch { items });
+        let items = ["a".repeat(10), "b".repeat(500), "c".repeat(10)];
+        let envelopes = split_by_size(&items, 200, batch);
+        let chunks: Vec<usize> = envelopes
+            .iter()
+            .map(|envelope| envelope.message().items.len())
+            .collect();
+        assert_eq!(chunks, [1, 1, 1]);
+        assert!(split_by_size(&[], 200, batch).is_empty());
+    }
+
+    #[test]
+    fn try_reply_needs_a_msg_id() {
+        let gossip = Envelope::new("n2", "n1", None, Message::Read { key: 1 }).without_msg_id();
+        let err = gossip.try_reply(Message::ReadOk { value: 1 }).unwrap_err();
+        assert_eq!(err.src, "n2");
+
+        let request = Envelope::new("c1", "n1", None, Message::Read { key: 1 });
+        let reply = request.try_reply(Message::ReadOk { value: 1 }).unwrap();
+        assert_eq!(reply.in_reply_to(), request.msg_id());
+    }
+
+    #[test]
+    fn in_reply_to_round_trips_all_three_ways() {
+        let cases = [
+            (
+                r#"{"in_reply_to":3,"type":"read_ok","value":1}"#,
+                InReplyTo::Present(3),
+            ),
+            (
+                r#"{"in_reply_to":null,"type":"read_ok","value":1}"#,
+                InReplyTo::Null,
+            ),
+            (r#"{"type":"read_ok","value":1}"#, InReplyTo::Absent),
+        ];
+        for (json, expected) in cases {
+            let body: Body<Message> = serde_json::from_str(json).unwrap();
+            assert_eq!(body.raw_in_reply_to(), expected);
+            assert_eq!(body.in_reply_to(), expected.get());
+            assert_eq!(serde_json::to_string(&body).unwrap(), json);
+        }
+    }
+
+    #[test]
+    fn msg_id_decodes_present_null_or_missing() {
+        let cases = [
+            (
+                r#"{"msg_id":5,"type":"read","key":1}"#,
+                Some(5),
+                r#"{"msg_id":5,"type":"read","key":1}"#,
+            ),
+            (
+                r#"{"msg_id":null,"type":"read","key":1}"#,
+                None,
+                r#"{"type":"read","key":1}"#,
+            ),
+            (
+                r#"{"type":"read","key":1}"#,
+                None,
+                r#"{"type":"read","key":1}"#,
+            ),
+        ];
+        for (json, expected, out) in cases {
+            let body: Body<Message> = serde_json::from_str(json).unwrap();
+            assert_eq!(body.msg_id(), expected);
+            assert_eq!(body.message(), &Message::Read { key: 1 });
+            assert_eq!(serde_json::to_string(&body).unwrap(), out);
+        }
+    }
+
+    #[test]
+    fn msg_id_is_optional_for_every_variant() {
+        let lines = [
+            r#"{"src":"c1","dest":"n1","body":{"type":"read","key":1}}"#,
+            r#"{"src":"n2","dest":"n1","body":{"msg_id":null,"type":"sync"}}"#,
+            r#"{"src":"n2","dest":"n1","body":{"msg_id":9,"in_reply_to":4,"type":"read_ok","value":3}}"#,
+        ];
+        let msg_ids: Vec<_> = lines
+            .iter()
+            .map(|line| {
+                serde_json::from_str::<Envelope<Message>>(line)
+                    .unwrap()
+                    .msg_id()
+            })
+            .collect();
+        assert_eq!(msg_ids, [None, None, Some(9)]);
+    }
+
+    #[test]
+    fn concurrent_sends_go_out_whole_and_with_their_own_msg_ids() {
+        let _capture = crate::capture::capturing();
+        let senders: Vec<_> = ["n1", "n2"]
+            .into_iter()
+            .map(|src| {
+                std::thread::spawn(move || {
+                    for value in 0..500 {
+                        Envelope::new(src, "c1", None, Message::ReadOk { value }).send();
+                    }
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.join().unwrap();
+        }
+
+        let sent = crate::drain_outbound();
+        assert_eq!(sent.len(), 1_000);
+        for src in ["n1", "n2"] {
+            // Each one intact, and in the order its thread sent it.
+            let values: Vec<_> = sent
+                .iter()
+                .filter(|envelope| envelope.src == src)
+                .map(|envelope| envelope.message()["value"].as_u64().unwrap())
+                .collect();
+            assert_eq!(values, (0..500).collect::<Vec<_>>());
+        }
+        let msg_ids: std::collections::HashSet<_> = sent
+            .iter()
+            .map(|envelope| envelope.msg_id().unwrap())
+            .collect();
+        assert_eq!(msg_ids.len(), sent.len());
+    }
+
+    /// What sending these two envelopes writes out, one per line.
+    fn written_lines() -> Vec<String> {
+        Envelope::new("n1", "c1", Some(1), Message::ReadOk { value: 1 }).send();
+        let echo = serde_json::json!({"type": "echo", "echo": "carriage\r\nreturn"});
+        Envelope::new("n1", "c1", None, echo).send();
+        let written = String::from_utf8(crate::capture::drain_outbound_bytes()).unwrap();
+        written.split_inclusive('\n').map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn every_line_ends_in_a_single_newline() {
+        let _capture = crate::capture::capturing();
+        let lines = written_lines();
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            assert!(line.ends_with('\n') && !line[..line.len() - 1].contains('\n'));
+            // Even the one in the payload is escaped.
+            assert!(!line.contains('\r'), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn crlf_is_there_for_those_who_ask() {
+        let _capture = crate::capture::capturing();
+        crate::RuntimeConfig {
+            line_ending: crate::LineEnding::CrLf,
+            ..Default::default()
+        }
+        .install()
+        .unwrap();
+        let lines = written_lines();
+        crate::RuntimeConfig::default().install().unwrap();
+
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            let payload = line.strip_suffix("\r\n").unwrap();
+            assert!(!payload.contains(['\r', '\n']), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn acked_now_and_answered_later() {
+        let _capture = crate::capture::capturing();
+        let request = Envelope::new("c1", "n1", None, Message::Read { key: 1 });
+        let token = request.ack_now_reply_later(Message::Sync { messages: vec![] });
+        let acked = crate::drain_outbound();
+        assert_eq!(acked.len(), 1, "the ack goes out right away");
+        assert_eq!(acked[0].message()["type"], "sync");
+        assert_eq!(acked[0].dest, "c1");
+        assert_eq!(acked[0].in_reply_to(), request.msg_id());
+
+        // Somewhere else entirely, once the answer's in.
+        token.complete(Message::ReadOk { value: 5 });
+        let answered = crate::drain_outbound();
+        assert_eq!(answered.len(), 1);
+        assert_eq!(answered[0].message()["value"], 5);
+        assert_eq!(
+            (answered[0].src.as_str(), answered[0].dest.as_str()),
+            ("n1", "c1")
+        );
+        assert_eq!(answered[0].in_reply_to(), request.msg_id());
+        assert_ne!(answered[0].msg_id(), acked[0].msg_id());
+    }
+
+    #[test]
+    fn forwarding_drops_in_reply_to() {
+        let lines = [
+            r#"{"src":"n2","dest":"n1","body":{"type":"read_ok","msg_id":8,"in_reply_to":3,"value":1}}"#,
+            r#"{"src":"n2","dest":"n1","body":{"type":"read_ok","msg_id":8,"in_reply_to":null,"value":1}}"#,
+        ];
+        for line in lines {
+            let answer: Envelope<Message> = serde_json::from_str(line).unwrap();
+            let forwarded = answer.forward_body("n3");
+            assert_eq!(forwarded.raw_in_reply_to(), InReplyTo::Absent);
+            assert_ne!(forwarded.msg_id(), answer.msg_id());
+            assert_eq!(forwarded.message(), answer.message());
+            let json = serde_json::to_value(&forwarded).unwrap();
+            assert!(json["body"].get("in_reply_to").is_none(), "{json}");
+        }
+    }
+
+    #[test]
+    fn proxied_reply_goes_back_to_the_client() {
+        // c1 -> n1 (proxy)
+        let request = Envelope::new("c1", "n1", None, Message::Read { key: 1 });
+        let token = request.defer();
+        // n1 -> n2 (backend)
+        let forwarded = request.forward_body("n2");
+        assert_eq!(
+            (forwarded.src.as_str(), forwarded.dest.as_str()),
+            ("n1", "n2")
+        );
+        // n2 -> n1
+        let answer = forwarded.reply(Message::ReadOk { value: 7 });
+        assert_eq!(answer.in_reply_to(), forwarded.msg_id());
+        // n1 -> c1, long after the fact.
+        let reply = answer.reply_to(&token, answer.message().clone());
+        assert_eq!((reply.src.as_str(), reply.dest.as_str()), ("n1", "c1"));
+        assert_eq!(reply.in_reply_to(), request.msg_id());
+        assert_eq!(reply.message(), &Message::ReadOk { value: 7 });
+        assert!(!reply.internal());
+    }
+
+    #[test]
+    fn reply_echoes_the_named_request_fields() {
+        let line =
+            r#"{"src":"c1","dest":"n1","body":{"type":"read","msg_id":3,"key":7,"trace":"t-1"}}"#;
+        let request: Envelope<Message> = serde_json::from_str(line).unwrap();
+        assert_eq!(request.message(), &Message::Read { key: 7 });
+
+        let reply = request.reply_echoing(
+            &["key", "trace", "value", "missing"],
+            Message::ReadOk { value: 9 },
+        );
+        assert_eq!(reply.message(), &Message::ReadOk { value: 9 });
+        let body = serde_json::to_value(&reply).unwrap()["body"].take();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "type": "read_ok",
+                "msg_id": reply.msg_id(),
+                "in_reply_to": 3,
+                "value": 9,
+                "key": 7,
+                "trace": "t-1",
+            })
+        );
+    }
+
+    #[test]
+    fn extra_fields_do_not_go_back_out() {
+        let line =
+            r#"{"src":"c1","dest":"n1","body":{"type":"read","msg_id":3,"key":7,"trace":"t-1"}}"#;
+        let request: Envelope<Message> = serde_json::from_str(line).unwrap();
+        assert_eq!(request.extra()["trace"], "t-1");
+
+        let forwarded = request.forward_body("n2");
+        assert_eq!(
+            serde_json::to_value(&forwarded).unwrap()["body"],
+            serde_json::json!({"type": "read", "msg_id": forwarded.msg_id(), "internal": true, "key": 7})
+        );
+    }
+
+    fn sync_with(fields: &str) -> String {
+        format!(r#"{{"src":"c1","dest":"n1","body":{{"type":"sync","msg_id":1{fields}}}}}"#)
+    }
+
+    #[test]
+    fn strict_parse_rejects_fields_we_dont_know() {
+        let line = sync_with(r#","messages":[1],"bogus":true"#);
+        let err = Envelope::<Message>::parse(&line, Strictness::Strict).unwrap_err();
+        assert!(err.reason.contains("`bogus`"), "{}", err.reason);
+        let reply = err.reply().unwrap();
+        assert_eq!(reply.message().code, ErrorCode::MalformedRequest);
+        assert_eq!(reply.in_reply_to(), Some(1));
+
+        let envelope = Envelope::<Message>::parse(&line, Strictness::Lenient).unwrap();
+        assert_eq!(envelope.message(), &Message::Sync { messages: vec![1] });
+
+        // Being null doesn't make it any less unknown.
+        let line = sync_with(r#","messages":[1],"bogus":null"#);
+        let err = Envelope::<Message>::parse(&line, Strictness::Strict).unwrap_err();
+        assert!(err.reason.contains("`bogus`"), "{}", err.reason);
+    }
+
+    #[test]
+    fn strict_parse_takes_fields_that_are_skipped_on_the_way_out() {
+        for fields in [
+            r#","messages":[]"#,
+            "",
+            r#","messages":[],"internal":false"#,
+            r#","messages":[],"in_reply_to":null"#,
+        ] {
+            let envelope = Envelope::<Message>::parse(&sync_with(fields), Strictness::Strict);
+            assert_eq!(
+                envelope.unwrap().message(),
+                &Message::Sync { messages: vec![] }
+            );
+        }
+        let line = sync_with(r#","messages":[],"bogus":[]"#);
+        assert!(Envelope::<Message>::parse(&line, Strictness::Strict).is_err());
+    }
+}
@@ -0,0 +1,509 @@
+use crate::{Address, Envelope, ReplyToken};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fmt, io,
+    panic::{self, UnwindSafe},
+};
+
+/// The error codes Maelstrom understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    Timeout,
+    NodeNotFound,
+    NotSupported,
+    TemporarilyUnavailable,
+    MalformedRequest,
+    Crash,
+    Abort,
+    KeyDoesNotExist,
+    KeyAlreadyExists,
+    PreconditionFailed,
+    TxnConflict,
+    /// Anything we don't know a name for. Maelstrom
+    /// leaves codes 1000 and above for users to define.
+    Other(usize),
+}
+
+impl ErrorCode {
+    pub fn code(&self) -> usize {
+        match self {
+            ErrorCode::Timeout => 0,
+            ErrorCode::NodeNotFound => 1,
+            ErrorCode::NotSupported => 10,
+            ErrorCode::TemporarilyUnavailable => 11,
+            ErrorCode::MalformedRequest => 12,
+            ErrorCode::Crash => 13,
+            ErrorCode::Abort => 14,
+            ErrorCode::KeyDoesNotExist => 20,
+            ErrorCode::KeyAlreadyExists => 21,
+            ErrorCode::PreconditionFailed => 22,
+            ErrorCode::TxnConflict => 30,
+            ErrorCode::Other(code) => *code,
+        }
+    }
+}
+
+impl From<usize> for ErrorCode {
+    fn from(code: usize) -> Self {
+        match code {
+            0 => ErrorCode::Timeout,
+            1 => ErrorCode::NodeNotFound,
+            10 => ErrorCode::NotSupported,
+            11 => ErrorCode::TemporarilyUnavailable,
+            12 => ErrorCode::MalformedRequest,
+            13 => ErrorCode::Crash,
+            14 => ErrorCode::Abort,
+            20 => ErrorCode::KeyDoesNotExist,
+            21 => ErrorCode::KeyAlreadyExists,
+            22 => ErrorCode::PreconditionFailed,
+            30 => ErrorCode::TxnConflict,
+            code => ErrorCode::Other(code),
+        }
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.code() as u64)
+    }
+}
+
+impl<'de> Deserialize<'de> for ErrorCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        usize::deserialize(deserializer).map(ErrorCode::from)
+    }
+}
+
+/// A body for Maelstrom's `error` message, for nodes
+/// whose own message types don't have one.
+///
+/// It's `#[non_exhaustive]`, so we can add fields later on without
+/// breaking anybody; build one with [ErrorMessage::new]. To take these in
+/// next to a node's own messages, try one after the other:
+///
+/// ```
+/// use maelstrom::{ErrorCode, ErrorMessage};
+/// use serde::Deserialize;
+/// use serde_json::json;
+///
+/// #[derive(Debug, Deserialize)]
+/// #[serde(tag = "type", rename_all = "snake_case")]
+/// enum Message {
+///     Read { key: usize },
+/// }
+///
+/// #[derive(Debug, Deserialize)]
+/// #[serde(untagged)]
+/// enum Inbound {
+///     Ours(Message),
+///     Error(ErrorMessage),
+/// }
+///
+/// let error = ErrorMessage::new(ErrorCode::KeyDoesNotExist, "no such key");
+/// let body = serde_json::to_value(&error).unwrap();
+/// assert_eq!(body, json!({"type": "error", "code": 20, "text": "no such key"}));
+/// match serde_json::from_value(body).unwrap() {
+///     Inbound::Error(decoded) => assert_eq!(decoded, error),
+///     other => panic!("decoded as {other:?}"),
+/// }
+///
+/// let read = json!({"type": "read", "key": 3});
+/// assert!(matches!(
+///     serde_json::from_value(read).unwrap(),
+///     Inbound::Ours(Message::Read { key: 3 })
+/// ));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "error")]
+#[non_exhaustive]
+pub struct ErrorMessage {
+    pub code: ErrorCode,
+    pub text: String,
+}
+
+impl ErrorMessage {
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        Self {
+            code,
+            text: text.into(),
+        }
+    }
+}
+
+/// Shorthands for answering a deferred request with an `error`, e.g.
+/// when a cas we tried on a client's behalf didn't go through.
+impl ReplyToken {
+    pub fn reply_error(&self, code: ErrorCode, text: impl Into<String>) -> Envelope<ErrorMessage> {
+        self.reply(ErrorMessage::new(code, text))
+    }
+
+    /// Code 22, e.g. a cas whose `from` didn't match.
+    pub fn reply_precondition_failed(&self, text: impl Into<String>) -> Envelope<ErrorMessage> {
+        self.reply_error(ErrorCode::PreconditionFailed, text)
+    }
+
+    /// Code 20.
+    pub fn reply_key_does_not_exist(&self, text: impl Into<String>) -> Envelope<ErrorMessage> {
+        self.reply_error(ErrorCode::KeyDoesNotExist, text)
+    }
+
+    /// Code 11, i.e. it's safe for the client to retry.
+    pub fn reply_temporarily_unavailable(&self, text: impl Into<String>) -> Envelope<ErrorMessage> {
+        self.reply_error(ErrorCode::TemporarilyUnavailable, text)
+    }
+
+    /// Code 10.
+    pub fn reply_not_supported(&self, text: impl Into<String>) -> Envelope<ErrorMessage> {
+        self.reply_error(ErrorCode::NotSupported, text)
+    }
+}
+
+/// An inbound message we couldn't make sense of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MalformedRequest {
+    /// Who to complain to, if the message was at least
+    /// well-formed enough to tell.
+    pub token: Option<ReplyToken>,
+    /// What was wrong with it.
+    pub reason: String,
+}
+
+impl MalformedRequest {
+    /// An `error` reply (code 12) for the sender of the malformed message.
+    pub fn reply(&self) -> Option<Envelope<ErrorMessage>> {
+        self.token
+            .as_ref()
+            .map(|token| token.reply_error(ErrorCode::MalformedRequest, &self.reason))
+    }
+}
+
+impl fmt::Display for MalformedRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Malformed request: {}", self.reason)
+    }
+}
+
+impl std::error::Error for MalformedRequest {}
+
+/// A message we were asked to reply to, but that didn't have
+/// a msg_id for our reply to be in reply to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoMsgId {
+    /// Whoever sent it.
+    pub src: Address,
+}
+
+impl fmt::Display for NoMsgId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Can't reply to a message from {} without a msg_id",
+            self.src
+        )
+    }
+}
+
+impl std::error::Error for NoMsgId {}
+
+/// What a fallible handler can fail with: either just the request it was
+/// handling, which gets an `error` reply, or the whole node, which stops.
+#[derive(Debug)]
+pub enum HandlerError<E = Box<dyn std::error::Error + Send + Sync>> {
+    /// Answer the request with this, and carry on with the next one.
+    Reply(ErrorMessage),
+    /// Give up on the node altogether.
+    Fatal(E),
+}
+
+impl<E> From<ErrorMessage> for HandlerError<E> {
+    fn from(error: ErrorMessage) -> Self {
+        Self::Reply(error)
+    }
+}
+
+impl<M: Serialize> Envelope<M> {
+    /// Deal with what handling this envelope came to: a [HandlerError::Reply]
+    /// is sent back to whoever sent it, and only a [HandlerError::Fatal]
+    /// makes it out, for the caller to stop on (e.g. with `?`).
+    pub fn reply_on_error<E>(
+        &self,
+        result: Result<(), HandlerError<E>>,
+    ) -> Result<(), RuntimeError<E>> {
+        match result {
+            Ok(()) => Ok(()),
+            Err(HandlerError::Reply(error)) => {
+                self.defer().reply(error).send();
+                Ok(())
+            }
+            Err(HandlerError::Fatal(err)) => Err(RuntimeError::Handler(err)),
+        }
+    }
+}
+
+/// What happens when a handler panics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Let it take the whole process down, like it would anyway.
+    #[default]
+    Abort,
+    /// Catch it, log it, answer the request with a `crash` error,
+    /// and carry on with the next one.
+    Isolate,
+}
+
+impl<M: Serialize> Envelope<M> {
+    /// Run `handler` for this envelope under `policy`. Returns whatever the
+    /// handler did, or `None` if it panicked and the panic was isolated.
+    ///
+    /// Isolating needs `handler` to be [UnwindSafe], which a closure that
+    /// borrows the node's state mutably isn't. Wrap it in
+    /// [std::panic::AssertUnwindSafe] if it's alright for the state to be
+    /// left halfway through an update, which is also why the reply is a
+    /// `crash` (i.e. it may or may not have happened) rather than a definite one.
+    pub fn handle_with<R>(
+        &self,
+        policy: PanicPolicy,
+        handler: impl FnOnce() -> R + UnwindSafe,
+    ) -> Option<R> {
+        if policy == PanicPolicy::Abort {
+            return Some(handler());
+        }
+        match panic::catch_unwind(handler) {
+            Ok(result) => Some(result),
+            Err(payload) => {
+                let reason = payload
+                    .downcast_ref::<&str>()
+                    .map(|reason| reason.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+                eprintln!("Handler panicked on a message from {}: {reason}", self.src);
+                self.defer().reply_error(ErrorCode::Crash, reason).send();
+                None
+            }
+        }
+    }
+}
+
+/// Why a node had to stop early.
+#[derive(Debug)]
+pub enum RuntimeError<E = Box<dyn std::error::Error + Send + Sync>> {
+    /// Reading stdin failed.
+    Io(io::Error),
+    /// A line on stdin wasn't a message we understand.
+    Serde(serde_json::Error),
+    /// The node itself gave up.
+    Handler(E),
+    /// Nobody is listening for inbound messages anymore.
+    ChannelClosed,
+}
+
+impl<E> From<io::Error> for RuntimeError<E> {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl<E> From<serde_json::Error> for RuntimeError<E> {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Serde(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for RuntimeError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Failed to read stdin: {err}"),
+            Self::Serde(err) => write!(f, "Failed to decode a message: {err}"),
+            Self::Handler(err) => write!(f, "Node failed: {err}"),
+            Self::ChannelClosed => write!(f, "Nobody is handling inbound messages anymore"),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for RuntimeError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Serde(err) => Some(err),
+            Self::Handler(_) | Self::ChannelClosed => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capture::capturing, drain_outbound};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Message {
+        Write { value: i64 },
+        WriteOk,
+        Error { code: ErrorCode, text: String },
+    }
+
+    #[derive(Debug)]
+    struct OutOfDisk;
+
+    fn sent() -> Vec<Envelope<Message>> {
+        drain_outbound()
+            .into_iter()
+            .map(|envelope| {
+                serde_json::from_value(serde_json::to_value(envelope).unwrap()).unwrap()
+            })
+            .collect()
+    }
+
+    /// Takes anything but negative values, and falls over on a zero.
+    fn write(envelope: &Envelope<Message>) -> Result<(), HandlerError<OutOfDisk>> {
+        let Message::Write { value } = envelope.message() else {
+            unreachable!()
+        };
+        match value {
+            0 => Err(HandlerError::Fatal(OutOfDisk)),
+            value if *value < 0 => {
+                Err(ErrorMessage::new(ErrorCode::PreconditionFailed, "negative").into())
+            }
+            _ => {
+                envelope.reply(Message::WriteOk).send();
+                Ok(())
+            }
+        }
+    }
+
+    fn write_from(client: &str, value: i64) -> Envelope<Message> {
+        Envelope::new(client, "n1", None, Message::Write { value })
+    }
+
+    #[test]
+    fn bad_request_gets_an_error_and_the_node_carries_on() {
+        let _capture = capturing();
+        let requests = [write_from("c1", -1), write_from("c2", 5)];
+        for request in &requests {
+            assert!(request.reply_on_error(write(request)).is_ok());
+        }
+
+        let replies = sent();
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0].dest, "c1");
+        assert_eq!(replies[0].in_reply_to(), requests[0].msg_id());
+        assert_eq!(
+            replies[0].message(),
+            &Message::Error {
+                code: ErrorCode::PreconditionFailed,
+                text: "negative".into()
+            }
+        );
+        assert_eq!(replies[1].dest, "c2");
+        assert_eq!(replies[1].in_reply_to(), requests[1].msg_id());
+        assert_eq!(replies[1].message(), &Message::WriteOk);
+    }
+
+    #[test]
+    fn fatal_error_stops_the_node_without_a_reply() {
+        let _capture = capturing();
+        let request = write_from("c1", 0);
+        assert!(matches!(
+            request.reply_on_error(write(&request)),
+            Err(RuntimeError::Handler(OutOfDisk))
+        ));
+        assert!(sent().is_empty());
+    }
+
+    #[test]
+    fn failed_cas_gets_answered_with_code_22_later() {
+        let _capture = capturing();
+        let request = write_from("c1", 5);
+        let token = request.defer();
+        token.reply_precondition_failed("expected 4, had 3").send();
+        token.reply_key_does_not_exist("no key").send();
+        token.reply_temporarily_unavailable("busy").send();
+        token.reply_not_supported("nope").send();
+
+        let replies = sent();
+        let codes: Vec<ErrorCode> = replies
+            .iter()
+            .map(|reply| match reply.message() {
+                Message::Error { code, .. } => *code,
+                other => panic!("not an error: {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            codes,
+            [
+                ErrorCode::PreconditionFailed,
+                ErrorCode::KeyDoesNotExist,
+                ErrorCode::TemporarilyUnavailable,
+                ErrorCode::NotSupported,
+            ]
+        );
+        assert_eq!(
+            replies[0].message(),
+            &Message::Error {
+                code: ErrorCode::PreconditionFailed,
+                text: "expected 4, had 3".into()
+            }
+        );
+        for reply in &replies {
+            assert_eq!((reply.src.as_str(), reply.dest.as_str()), ("n1", "c1"));
+            assert_eq!(reply.in_reply_to(), request.msg_id());
+        }
+    }
+
+    /// Counts the writes it's seen, and panics on a zero.
+    #[derive(Default)]
+    struct Node {
+        writes: usize,
+    }
+
+    impl Node {
+        fn handle(&mut self, envelope: &Envelope<Message>) {
+            let Message::Write { value } = envelope.message() else {
+                unreachable!()
+            };
+            assert_ne!(*value, 0, "zero");
+            self.writes += 1;
+            envelope.reply(Message::WriteOk).send();
+        }
+    }
+
+    #[test]
+    fn isolated_panic_replies_crash_and_nodes_carry_on() {
+        let _capture = capturing();
+        let (mut panicky, mut sibling) = (Node::default(), Node::default());
+        let requests = [
+            (0, write_from("c1", 0)),
+            (1, write_from("c2", 1)),
+            (0, write_from("c3", 2)),
+        ];
+        for (node, request) in &requests {
+            let node = if *node == 0 {
+                &mut panicky
+            } else {
+                &mut sibling
+            };
+            let handled = request.handle_with(
+                PanicPolicy::Isolate,
+                panic::AssertUnwindSafe(|| node.handle(request)),
+            );
+            assert_eq!(handled.is_some(), request.src != "c1");
+        }
+
+        let replies = sent();
+        assert_eq!(replies.len(), 3);
+        assert!(matches!(
+            replies[0].message(),
+            Message::Error {
+                code: ErrorCode::Crash,
+                text
+            } if text.contains("zero")
+        ));
+        assert_eq!(replies[0].in_reply_to(), requests[0].1.msg_id());
+        for (reply, (_, request)) in replies.iter().zip(&requests).skip(1) {
+            assert_eq!(reply.message(), &Message::WriteOk);
+            assert_eq!(reply.dest, request.src);
+        }
+        assert_eq!((panicky.writes, sibling.writes), (1, 1));
+    }
+}
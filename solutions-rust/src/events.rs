@@ -0,0 +1,510 @@
+use crate::{Address, Envelope};
+use serde::Serialize;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    fmt,
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+/// Anything a node's event loop might have to react to.
+#[derive(Debug)]
+pub enum Event<T> {
+    /// Something came in.
+    Message(T),
+    /// The tick interval elapsed, e.g. time to flush buffers.
+    Tick,
+    /// Something we scheduled for later is due.
+    Scheduled(T),
+    /// An rpc we were waiting on (see [Events::await_reply])
+    /// went unanswered for too long.
+    Timeout { msg_id: usize, dest: Address },
+    /// We were told to expect an `init` (see [Events::with_init_timeout]),
+    /// and it didn't come in time. Only ever happens the once.
+    InitTimeout,
+    /// Nothing else is ever going to come in.
+    Shutdown,
+}
+
+/// Runs on the node's final state `A` as it goes away (see [Events::with_shutdown_hook]).
+pub type ShutdownHook<A> = Box<dyn FnOnce(&A) + Send>;
+
+/// Merges inbound messages, periodic ticks, and messages scheduled
+/// for later into a single stream of [Event]s, so a node can be
+/// written as one `match events.next_event()` loop.
+///
+/// `A` is the node's own state, for any shutdown hooks to look at
+/// (see [Events::with_shutdown_hook]).
+pub struct Events<T, A = ()> {
+    rx: Receiver<T>,
+    tick_interval: Duration,
+    next_tick: Instant,
+    scheduled: Vec<(Instant, T)>,
+    /// The rpcs we're waiting on, by msg_id, with when we give up on them.
+    awaiting: HashMap<usize, (Instant, Address)>,
+    shut_down: bool,
+    init_deadline: Option<Instant>,
+    shutdown_hooks: Vec<ShutdownHook<A>>,
+    timers: Vec<NamedTimer>,
+    /// When each timer (by index) fires next, soonest first.
+    due: BinaryHeap<Reverse<(Instant, usize)>>,
+}
+
+struct NamedTimer {
+    name: &'static str,
+    interval: Duration,
+    callback: Box<dyn FnMut() + Send>,
+}
+
+impl<T: fmt::Debug, A> fmt::Debug for Events<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Events")
+            .field("rx", &self.rx)
+            .field("tick_interval", &self.tick_interval)
+            .field("next_tick", &self.next_tick)
+            .field("scheduled", &self.scheduled)
+            .field("awaiting", &self.awaiting)
+            .field("shut_down", &self.shut_down)
+            .field("init_deadline", &self.init_deadline)
+            .field("shutdown_hooks", &self.shutdown_hooks.len())
+            .field(
+                "timers",
+                &self
+                    .timers
+                    .iter()
+                    .map(|timer| timer.name)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl<T> Events<T> {
+    pub fn new(rx: Receiver<T>, tick_interval: Duration) -> Self {
+        Self::for_node(rx, tick_interval)
+    }
+
+    /// Wait for whatever happens next (see [Events::next_event_with]).
+    pub fn next_event(&mut self) -> Event<T> {
+        self.next_event_with(&())
+    }
+}
+
+impl<T, A> Events<T, A> {
+    /// Like [Events::new], but with shutdown hooks that get
+    /// to look at the node's state `A` (see [Events::with_shutdown_hook]).
+    pub fn for_node(rx: Receiver<T>, tick_interval: Duration) -> Self {
+        Self {
+            rx,
+            tick_interval,
+            next_tick: Instant::now() + tick_interval,
+            scheduled: Vec::new(),
+            awaiting: HashMap::new(),
+            shut_down: false,
+            init_deadline: None,
+            shutdown_hooks: Vec::new(),
+            timers: Vec::new(),
+            due: BinaryHeap::new(),
+        }
+    }
+
+    /// Hand back an [Event::InitTimeout] if we aren't told that the `init`
+    /// arrived (see [Events::init_arrived]) within `timeout`. Maelstrom
+    /// always starts with an `init`, so if it hasn't come by then, we're
+    /// most likely misconfigured, and the node should give up.
+    pub fn with_init_timeout(mut self, timeout: Duration) -> Self {
+        self.init_deadline = Some(Instant::now() + timeout);
+        self
+    }
+
+    /// The `init` came in, so stop waiting for it.
+    pub fn init_arrived(&mut self) {
+        self.init_deadline = None;
+    }
+
+    /// Run `hook` on the node's final state once we're going away, e.g. to
+    /// dump some stats. Hooks run in the order they were added, right before
+    /// [Events::next_event_with] hands back an [Event::Shutdown].
+    pub fn with_shutdown_hook(mut self, hook: impl FnOnce(&A) + Send + 'static) -> Self {
+        self.shutdown_hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Run the shutdown hooks on `state` now, e.g. when giving up after an
+    /// [Event::InitTimeout]. Any hook only ever runs once, so they won't
+    /// run again on the way out.
+    pub fn run_shutdown_hooks(&mut self, state: &A) {
+        for hook in self.shutdown_hooks.drain(..) {
+            hook(state);
+        }
+    }
+
+    /// Call `callback` every `interval`, on top of (and independently of) the
+    /// regular tick, e.g. a quick ack flush next to a slow anti-entropy sweep.
+    /// Timers run inside [Events::next_event], before it hands back anything else.
+    pub fn register_timer(
+        &mut self,
+        name: &'static str,
+        interval: Duration,
+        callback: impl FnMut() + Send + 'static,
+    ) {
+        self.due
+            .push(Reverse((Instant::now() + interval, self.timers.len())));
+        self.timers.push(NamedTimer {
+            name,
+            interval,
+            callback: Box::new(callback),
+        });
+    }
+
+    /// Run every timer that's due, and line each one up for next time.
+    fn run_due_timers(&mut self, now: Instant) {
+        while let Some(&Reverse((at, index))) = self.due.peek() {
+            if at > now {
+                break;
+            }
+            self.due.pop();
+            let timer = &mut self.timers[index];
+            (timer.callback)();
+            // Same as ticks: no bursts to catch up if we fell behind.
+            let mut next = at + timer.interval;
+            if next <= now {
+                next = now + timer.interval;
+            }
+            self.due.push(Reverse((next, index)));
+        }
+    }
+
+    /// Hand `item` back as an [Event::Scheduled] once `delay` has passed.
+    pub fn schedule(&mut self, delay: Duration, item: T) {
+        self.scheduled.push((Instant::now() + delay, item));
+    }
+
+    /// Hand back an [Event::Timeout] if `envelope` isn't answered within
+    /// `timeout`. Replies aren't matched up on their own, so tell us
+    /// about them with [Events::reply_arrived].
+    pub fn await_reply<M: Serialize>(&mut self, envelope: &Envelope<M>, timeout: Duration) {
+        if let Some(msg_id) = envelope.msg_id() {
+            self.awaiting
+                .insert(msg_id, (Instant::now() + timeout, envelope.dest.clone()));
+        }
+    }
+
+    /// A reply to `in_reply_to` came in, so stop waiting on it.
+    /// Returns whether we were.
+    pub fn reply_arrived(&mut self, in_reply_to: usize) -> bool {
+        self.awaiting.remove(&in_reply_to).is_some()
+    }
+
+    /// Fire the next [Event::Tick] right away instead of waiting for it,
+    /// e.g. to gossip something important now. Ticks carry on one
+    /// interval apart from there.
+    pub fn request_flush(&mut self) {
+        self.next_tick = Instant::now();
+    }
+
+    /// Tick every `interval` from now on, e.g. with whatever an
+    /// [crate::AdaptiveInterval] thinks is best. If that's sooner than
+    /// the next tick was due, the next tick gets pulled in too.
+    pub fn set_tick_interval(&mut self, interval: Duration) {
+        self.tick_interval = interval;
+        self.next_tick = self.next_tick.min(Instant::now() + interval);
+    }
+
+    /// Wait for whatever happens next.
+    ///
+    /// Anything due (scheduled items, timeouts, then the tick) comes before whatever
+    /// is waiting in the channel, so even with messages coming in non-stop,
+    /// a tick is never later than its deadline plus however long handling
+    /// the one message before it took.
+    ///
+    /// Once it's handed back an [Event::Shutdown], that's all it ever hands
+    /// back. The shutdown hooks run on `state` (the node's, as it is by then)
+    /// right before the first one, so there's no forgetting to run them.
+    pub fn next_event_with(&mut self, state: &A) -> Event<T> {
+        let event = self.wait();
+        if matches!(event, Event::Shutdown) {
+            self.run_shutdown_hooks(state);
+        }
+        event
+    }
+
+    fn wait(&mut self) -> Event<T> {
+        loop {
+            if self.shut_down {
+                return Event::Shutdown;
+            }
+
+            let now = Instant::now();
+            self.run_due_timers(now);
+
+            if let Some(position) = self.scheduled.iter().position(|(at, _)| *at <= now) {
+                return Event::Scheduled(self.scheduled.swap_remove(position).1);
+            }
+
+            if let Some(msg_id) = self
+                .awaiting
+                .iter()
+                .find(|(_, (deadline, _))| *deadline <= now)
+                .map(|(msg_id, _)| *msg_id)
+            {
+                let (_, dest) = self.awaiting.remove(&msg_id).unwrap();
+                return Event::Timeout { msg_id, dest };
+            }
+
+            if now >= self.next_tick {
+                self.next_tick += self.tick_interval;
+                // If we fell way behind, don't fire a burst of ticks to catch up.
+                if self.next_tick <= now {
+                    self.next_tick = now + self.tick_interval;
+                }
+                return Event::Tick;
+            }
+
+            if self.init_deadline.is_some_and(|deadline| now >= deadline) {
+                self.init_deadline = None;
+                return Event::InitTimeout;
+            }
+
+            let wake_up_at = self
+                .scheduled
+                .iter()
+                .map(|(at, _)| *at)
+                .chain(self.awaiting.values().map(|(deadline, _)| *deadline))
+                .chain(self.init_deadline)
+                .chain(self.due.peek().map(|Reverse((at, _))| *at))
+                .fold(self.next_tick, Instant::min);
+
+            match self.rx.recv_timeout(wake_up_at - now) {
+                Ok(item) => return Event::Message(item),
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.shut_down = true;
+                    return Event::Shutdown;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::channel,
+        Arc,
+    };
+
+    #[test]
+    fn timers_fire_at_their_own_rates() {
+        let (_tx, rx) = channel::<()>();
+        let mut events = Events::new(rx, Duration::from_millis(50));
+        let (fast, slow) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let counter = fast.clone();
+        events.register_timer("fast", Duration::from_millis(20), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let counter = slow.clone();
+        events.register_timer("slow", Duration::from_millis(100), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_millis(510) {
+            events.next_event();
+        }
+        let elapsed = started.elapsed().as_millis() as usize;
+        let (fast, slow) = (fast.load(Ordering::SeqCst), slow.load(Ordering::SeqCst));
+        // Never early, and never more than a few behind (missed
+        // fires aren't made up for, and a busy machine can stall us).
+        assert!(
+            fast <= elapsed / 20 && fast + 5 >= elapsed / 20,
+            "fast fired {fast} times in {elapsed}ms"
+        );
+        assert!(
+            slow <= elapsed / 100 && slow + 1 >= elapsed / 100,
+            "slow fired {slow} times in {elapsed}ms"
+        );
+    }
+
+    #[test]
+    fn ticks_keep_time_with_the_channel_flooded() {
+        let (tx, rx) = channel();
+        let interval = Duration::from_millis(20);
+        let handler = Duration::from_millis(3);
+        // Far more than we can get through, so there's always one waiting.
+        for n in 0..1_000 {
+            tx.send(n).unwrap();
+        }
+        let mut events = Events::new(rx, interval);
+
+        let started = Instant::now();
+        let (mut ticks, mut handled) = (vec![started], 0);
+        // What sleeping for `handler` actually took, at worst.
+        let mut slowest = Duration::ZERO;
+        while started.elapsed() < Duration::from_millis(300) {
+            match events.next_event() {
+                Event::Message(_) => {
+                    handled += 1;
+                    let at = Instant::now();
+                    std::thread::sleep(handler);
+                    slowest = slowest.max(at.elapsed());
+                }
+                Event::Tick => ticks.push(Instant::now()),
+                _ => {}
+            }
+        }
+
+        assert!(handled > 50 && handled < 1_000, "handled {handled}");
+        assert!(ticks.len() > 10, "only {} ticks", ticks.len() - 1);
+        // A few ms on top, for the scheduler's sake.
+        let slack = interval + slowest + Duration::from_millis(5);
+        for pair in ticks.windows(2) {
+            let gap = pair[1] - pair[0];
+            assert!(gap <= slack, "ticks {gap:?} apart");
+        }
+    }
+
+    #[test]
+    fn unanswered_requests_time_out() {
+        let (_tx, rx) = channel::<()>();
+        let mut events = Events::new(rx, Duration::from_secs(10));
+        let request = Envelope::new("n1", "n2", None, serde_json::json!({"type": "read"}));
+        let started = Instant::now();
+        events.await_reply(&request, Duration::from_millis(30));
+
+        match events.next_event() {
+            Event::Timeout { msg_id, dest } => {
+                assert_eq!(Some(msg_id), request.msg_id());
+                assert_eq!(dest, "n2");
+            }
+            other => panic!("expected a timeout, got {other:?}"),
+        }
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        // It's given up on, so a late reply isn't expected anymore.
+        assert!(!events.reply_arrived(request.msg_id().unwrap()));
+    }
+
+    #[test]
+    fn replies_in_time_cancel_the_timeout() {
+        let (_tx, rx) = channel::<()>();
+        let mut events = Events::new(rx, Duration::from_millis(60));
+        let request = Envelope::new("n1", "n2", None, serde_json::json!({"type": "read"}));
+        events.await_reply(&request, Duration::from_millis(20));
+        assert!(events.reply_arrived(request.msg_id().unwrap()));
+
+        // Well past the timeout, and all there is, is the tick.
+        assert!(matches!(events.next_event(), Event::Tick));
+    }
+
+    #[test]
+    fn a_requested_flush_comes_before_the_next_tick() {
+        let (tx, rx) = channel();
+        let interval = Duration::from_millis(200);
+        let mut events = Events::new(rx, interval);
+        tx.send("waiting").unwrap();
+
+        let started = Instant::now();
+        events.request_flush();
+        // Ahead of the message that was already waiting, too.
+        assert!(matches!(events.next_event(), Event::Tick));
+        assert!(started.elapsed() < interval / 2);
+        assert!(matches!(events.next_event(), Event::Message("waiting")));
+
+        // And the tick after it is a whole interval on from the flush.
+        assert!(matches!(events.next_event(), Event::Tick));
+        assert!(started.elapsed() >= interval);
+    }
+
+    #[test]
+    fn init_timeout_fires_once_unless_init_arrives() {
+        let (tx, rx) = channel();
+        let mut events =
+            Events::new(rx, Duration::from_secs(10)).with_init_timeout(Duration::from_millis(50));
+        let started = Instant::now();
+        tx.send("not an init").unwrap();
+        assert!(matches!(events.next_event(), Event::Message("not an init")));
+        assert!(matches!(events.next_event(), Event::InitTimeout));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        tx.send("late init").unwrap();
+        assert!(matches!(events.next_event(), Event::Message("late init")));
+
+        let (tx, rx) = channel();
+        let mut events =
+            Events::new(rx, Duration::from_millis(20)).with_init_timeout(Duration::from_millis(50));
+        tx.send("init").unwrap();
+        assert!(matches!(events.next_event(), Event::Message("init")));
+        events.init_arrived();
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_millis(100) {
+            assert!(matches!(events.next_event(), Event::Tick));
+        }
+    }
+
+    #[test]
+    fn shutdown_hooks_see_the_final_state() {
+        let (tx, rx) = channel();
+        let seen = Arc::new(AtomicUsize::new(0));
+        let hook_seen = seen.clone();
+        let mut events = Events::for_node(rx, Duration::from_secs(10)).with_shutdown_hook(
+            move |total: &usize| {
+                hook_seen.store(*total, Ordering::SeqCst);
+            },
+        );
+        for delta in [1, 2, 3] {
+            tx.send(delta).unwrap();
+        }
+        drop(tx);
+
+        let mut total = 0;
+        loop {
+            match events.next_event_with(&total) {
+                Event::Message(delta) => total += delta,
+                Event::Shutdown => break,
+                _ => {}
+            }
+        }
+        assert_eq!(seen.load(Ordering::SeqCst), 6);
+
+        // Hooks only run the once.
+        assert!(matches!(events.next_event_with(&100), Event::Shutdown));
+        events.run_shutdown_hooks(&100);
+        assert_eq!(seen.load(Ordering::SeqCst), 6);
+    }
+
+    #[test]
+    fn stateless_hooks_run_once_stdin_closes() {
+        let (tx, rx) = channel::<()>();
+        let ran = Arc::new(AtomicUsize::new(0));
+        let hook_ran = ran.clone();
+        let mut events = Events::new(rx, Duration::from_secs(10)).with_shutdown_hook(move |_| {
+            hook_ran.fetch_add(1, Ordering::SeqCst);
+        });
+        drop(tx);
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+        for _ in 0..3 {
+            assert!(matches!(events.next_event(), Event::Shutdown));
+        }
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn nothing_comes_after_shutdown() {
+        let (tx, rx) = channel::<()>();
+        let mut events = Events::new(rx, Duration::from_millis(5));
+        events.schedule(Duration::ZERO, ());
+        drop(tx);
+        assert!(matches!(events.next_event(), Event::Scheduled(())));
+        assert!(matches!(events.next_event(), Event::Shutdown));
+
+        // Well past the next tick, with something else due too.
+        events.schedule(Duration::ZERO, ());
+        std::thread::sleep(Duration::from_millis(20));
+        for _ in 0..3 {
+            assert!(matches!(events.next_event(), Event::Shutdown));
+        }
+    }
+}
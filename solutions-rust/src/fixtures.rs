@@ -0,0 +1,96 @@
+use crate::Envelope;
+use serde_json::{json, Value};
+
+/// Who the messages built here come from.
+const CLIENT: &str = "c1";
+
+fn from_client(dest: &str, msg_id: usize, body: Value) -> Envelope<Value> {
+    let mut envelope = json!({ "src": CLIENT, "dest": dest, "body": body });
+    envelope["body"]["msg_id"] = msg_id.into();
+    serde_json::from_value(envelope).unwrap()
+}
+
+/// The `init` Maelstrom starts every node off with, for trying nodes out
+/// without Maelstrom (with the `test-util` feature on). Serialize it to get the line Maelstrom would send,
+/// or decode it (e.g. with `serde_json::from_value`) as a node's own message.
+pub fn init_msg(node_id: &str, node_ids: &[&str], msg_id: usize) -> Envelope<Value> {
+    from_client(
+        node_id,
+        msg_id,
+        json!({ "type": "init", "node_id": node_id, "node_ids": node_ids }),
+    )
+}
+
+/// A client's `echo` (see [init_msg]).
+pub fn echo_msg(dest: &str, echo: &str, msg_id: usize) -> Envelope<Value> {
+    from_client(dest, msg_id, json!({ "type": "echo", "echo": echo }))
+}
+
+/// A client's `broadcast` (see [init_msg]).
+pub fn broadcast_msg(dest: &str, message: usize, msg_id: usize) -> Envelope<Value> {
+    from_client(
+        dest,
+        msg_id,
+        json!({ "type": "broadcast", "message": message }),
+    )
+}
+
+/// A client's `read` (see [init_msg]).
+pub fn read_msg(dest: &str, msg_id: usize) -> Envelope<Value> {
+    from_client(dest, msg_id, json!({ "type": "read" }))
+}
+
+/// A client's `generate` (see [init_msg]).
+pub fn generate_msg(dest: &str, msg_id: usize) -> Envelope<Value> {
+    from_client(dest, msg_id, json!({ "type": "generate" }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NodeId;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Message {
+        Init {
+            node_id: NodeId,
+            node_ids: Vec<NodeId>,
+        },
+        Echo {
+            echo: String,
+        },
+        Broadcast {
+            message: usize,
+        },
+    }
+
+    fn decoded(envelope: Envelope<Value>) -> Envelope<Message> {
+        let line = serde_json::to_string(&envelope).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn init_decodes_as_a_nodes_own_init() {
+        let init = decoded(init_msg("n1", &["n1", "n2"], 1));
+        assert_eq!((init.src.as_str(), init.dest.as_str()), ("c1", "n1"));
+        assert_eq!(init.msg_id(), Some(1));
+        assert_eq!(
+            init.message(),
+            &Message::Init {
+                node_id: "n1".into(),
+                node_ids: vec!["n1".into(), "n2".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn client_messages_decode_too() {
+        let echo = decoded(echo_msg("n1", "hi", 2));
+        assert_eq!(echo.message(), &Message::Echo { echo: "hi".into() });
+        let broadcast = decoded(broadcast_msg("n2", 7, 3));
+        assert_eq!(broadcast.message(), &Message::Broadcast { message: 7 });
+        assert_eq!(broadcast.msg_id(), Some(3));
+    }
+}
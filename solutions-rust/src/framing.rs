@@ -0,0 +1,191 @@
+use crate::LineEnding;
+use serde::Deserialize;
+use std::io::{self, BufRead, ErrorKind, Write};
+
+/// How messages are told apart on a byte stream.
+///
+/// Maelstrom talks newline-delimited json over stdio ([LineFramer]), but
+/// anything that can't promise there are no newlines inside a payload
+/// (e.g. a tcp link between our own nodes) is better off with [LengthPrefixed].
+pub trait Framer {
+    /// The next frame's payload, or `None` if the stream ended cleanly.
+    fn read_frame<R: BufRead>(&self, reader: &mut R) -> io::Result<Option<Vec<u8>>>;
+
+    fn write_frame<W: Write>(&self, writer: &mut W, payload: &[u8]) -> io::Result<()>;
+}
+
+/// One frame per line, which is what stdio uses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineFramer {
+    /// What we end frames with. Either one is understood when reading.
+    pub line_ending: LineEnding,
+}
+
+impl Framer for LineFramer {
+    fn read_frame<R: BufRead>(&self, reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+        let mut frame = Vec::new();
+        loop {
+            match reader.read_until(b'\n', &mut frame) {
+                Ok(0) if frame.is_empty() => return Ok(None),
+                Ok(_) => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        if frame.ends_with(b"\n") {
+            frame.pop();
+        }
+        if frame.ends_with(b"\r") {
+            frame.pop();
+        }
+        Ok(Some(frame))
+    }
+
+    fn write_frame<W: Write>(&self, writer: &mut W, payload: &[u8]) -> io::Result<()> {
+        writer.write_all(payload)?;
+        writer.write_all(self.line_ending.as_bytes())
+    }
+}
+
+/// Every frame is its payload's length (as a big-endian u32), then
+/// the payload itself, so the payload can hold any bytes at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthPrefixed {
+    /// The longest payload we'll read. Anything claiming to be longer is
+    /// more likely garbage than a message, and not worth allocating for.
+    pub max_frame_bytes: usize,
+}
+
+impl Default for LengthPrefixed {
+    fn default() -> Self {
+        Self {
+            max_frame_bytes: 16 << 20,
+        }
+    }
+}
+
+impl Framer for LengthPrefixed {
+    fn read_frame<R: BufRead>(&self, reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            match reader.fill_buf() {
+                Ok([]) => return Ok(None),
+                Ok(_) => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        let mut length = [0; 4];
+        reader.read_exact(&mut length)?;
+        let length = u32::from_be_bytes(length) as usize;
+        if length > self.max_frame_bytes {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("frame of {length} bytes is over {}", self.max_frame_bytes),
+            ));
+        }
+        let mut frame = vec![0; length];
+        reader.read_exact(&mut frame)?;
+        Ok(Some(frame))
+    }
+
+    fn write_frame<W: Write>(&self, writer: &mut W, payload: &[u8]) -> io::Result<()> {
+        let length = u32::try_from(payload.len())
+            .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "frame too long"))?;
+        writer.write_all(&length.to_be_bytes())?;
+        writer.write_all(payload)
+    }
+}
+
+/// Which [Framer] stdio goes through (see [crate::RuntimeConfig::framing]).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    /// [LineFramer], ending lines with [crate::RuntimeConfig::line_ending].
+    /// Leave this alone when running under Maelstrom.
+    #[default]
+    Lines,
+    /// [LengthPrefixed], with its default cap on frame sizes.
+    LengthPrefixed,
+}
+
+impl Framer for Framing {
+    fn read_frame<R: BufRead>(&self, reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            Self::Lines => LineFramer::default().read_frame(reader),
+            Self::LengthPrefixed => LengthPrefixed::default().read_frame(reader),
+        }
+    }
+
+    fn write_frame<W: Write>(&self, writer: &mut W, payload: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Lines => LineFramer {
+                line_ending: crate::config::line_ending(),
+            }
+            .write_frame(writer, payload),
+            Self::LengthPrefixed => LengthPrefixed::default().write_frame(writer, payload),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOADS: [&[u8]; 3] = [br#"{"text":"one line"}"#, b"{\"text\":\"two\nlines\"}", b""];
+
+    fn written<Fr: Framer>(framer: &Fr) -> Vec<u8> {
+        let mut stream = Vec::new();
+        for payload in PAYLOADS {
+            framer.write_frame(&mut stream, payload).unwrap();
+        }
+        stream
+    }
+
+    #[test]
+    fn length_prefixed_frames_can_hold_newlines() {
+        let framer = LengthPrefixed::default();
+        let stream = written(&framer);
+        let mut reader = &stream[..];
+        for payload in PAYLOADS {
+            assert_eq!(framer.read_frame(&mut reader).unwrap().unwrap(), payload);
+        }
+        assert!(framer.read_frame(&mut reader).unwrap().is_none());
+
+        let mut frames = Vec::new();
+        crate::for_each_frame(&stream[..], &framer, |frame| {
+            frames.push(frame.to_owned());
+            Ok::<_, io::Error>(())
+        })
+        .unwrap();
+        assert_eq!(frames[1], "{\"text\":\"two\nlines\"}");
+    }
+
+    #[test]
+    fn lines_cannot() {
+        let framer = LineFramer::default();
+        let stream = written(&framer);
+        let mut reader = &stream[..];
+        let mut frames = Vec::new();
+        while let Some(frame) = framer.read_frame(&mut reader).unwrap() {
+            frames.push(frame);
+        }
+        assert_eq!(frames.len(), 4, "the newline splits a payload in two");
+    }
+
+    #[test]
+    fn length_prefixed_frames_over_the_cap_are_invalid() {
+        let framer = LengthPrefixed { max_frame_bytes: 8 };
+        let mut stream = Vec::new();
+        framer.write_frame(&mut stream, b"0123456789").unwrap();
+        // Nothing past the length prefix, so it can't have tried to read the rest.
+        let err = framer.read_frame(&mut &stream[..4]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let mut small = Vec::new();
+        framer.write_frame(&mut small, b"01234567").unwrap();
+        assert_eq!(
+            framer.read_frame(&mut &small[..]).unwrap().unwrap(),
+            b"01234567"
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use crate::{rng::SplitMix64, NodeId};
+use std::{
+    collections::BTreeMap,
+    hash::{BuildHasherDefault, Hasher},
+};
+
+/// How many points on the ring each node gets. More of them
+/// spread keys more evenly across nodes.
+const POINTS_PER_NODE: usize = 64;
+
+/// Picks which node owns a key, e.g. to shard keys across the cluster.
+///
+/// Every node gets the same answer without talking to each other, and
+/// when a node comes or goes, only the keys it owned (or now owns) move.
+#[derive(Debug, Clone, Default)]
+pub struct ConsistentHash {
+    ring: BTreeMap<u64, NodeId>,
+}
+
+impl ConsistentHash {
+    pub fn new(node_ids: &[NodeId]) -> Self {
+        let mut hash = Self::default();
+        for node_id in node_ids {
+            hash.add_node(node_id);
+        }
+        hash
+    }
+
+    pub fn add_node(&mut self, node_id: &NodeId) {
+        for point in 0..POINTS_PER_NODE {
+            self.ring
+                .insert(stable_hash(&(node_id.as_str(), point)), node_id.clone());
+        }
+    }
+
+    pub fn remove_node(&mut self, node_id: &NodeId) {
+        self.ring.retain(|_, owner| owner != node_id);
+    }
+
+    /// Switch over to the nodes in `node_ids`, and work out which of `keys`
+    /// now belong to somebody else, e.g. to hand their state over. Only keys
+    /// that moved come back; with a node added, that's about 1/n of them,
+    /// all going to the new node.
+    pub fn rebalance<K: StableHash>(
+        &mut self,
+        node_ids: &[NodeId],
+        keys: impl IntoIterator<Item = K>,
+    ) -> Vec<Handoff<K>> {
+        let before: Vec<_> = keys
+            .into_iter()
+            .map(|key| {
+                let from = self.owner_of(&key).cloned();
+                (key, from)
+            })
+            .collect();
+        *self = Self::new(node_ids);
+
+        before
+            .into_iter()
+            .filter_map(|(key, from)| {
+                let to = self.owner_of(&key)?.clone();
+                (from.as_ref() != Some(&to)).then_some(Handoff { key, from, to })
+            })
+            .collect()
+    }
+
+    /// Who owns `key`, unless there's nobody to own it.
+    pub fn owner_of<K: StableHash + ?Sized>(&self, key: &K) -> Option<&NodeId> {
+        let point = stable_hash(key);
+        self.ring
+            .range(point..)
+            .chain(self.ring.iter())
+            .map(|(_, owner)| owner)
+            .next()
+    }
+}
+
+/// A key that changed hands in a [ConsistentHash::rebalance].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handoff<K> {
+    pub key: K,
+    /// Who used to own it, if anybody did.
+    pub from: Option<NodeId>,
+    pub to: NodeId,
+}
+
+/// Something that can be hashed the same way on any machine, whichever Rust
+/// version built it. [Hash] can't promise that: integers go in with the
+/// platform's width and byte order, and how a `str` is fed in is up to std.
+pub trait StableHash {
+    /// Feed our bytes to `hasher`, spelled out: integers as 8 little-endian
+    /// bytes, strings as their length and then their utf-8 bytes.
+    fn stable_hash_into<H: Hasher>(&self, hasher: &mut H);
+}
+
+macro_rules! stable_hash_as_u64 {
+    ($($int:ty),*) => {
+        $(
+            impl StableHash for $int {
+                fn stable_hash_into<H: Hasher>(&self, hasher: &mut H) {
+                    hasher.write(&(*self as u64).to_le_bytes());
+                }
+            }
+        )*
+    };
+}
+
+stable_hash_as_u64!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl StableHash for str {
+    fn stable_hash_into<H: Hasher>(&self, hasher: &mut H) {
+        self.len().stable_hash_into(hasher);
+        hasher.write(self.as_bytes());
+    }
+}
+
+impl StableHash for String {
+    fn stable_hash_into<H: Hasher>(&self, hasher: &mut H) {
+        self.as_str().stable_hash_into(hasher);
+    }
+}
+
+impl StableHash for NodeId {
+    fn stable_hash_into<H: Hasher>(&self, hasher: &mut H) {
+        self.as_str().stable_hash_into(hasher);
+    }
+}
+
+impl<T: StableHash + ?Sized> StableHash for &T {
+    fn stable_hash_into<H: Hasher>(&self, hasher: &mut H) {
+        (**self).stable_hash_into(hasher);
+    }
+}
+
+impl<A: StableHash, B: StableHash> StableHash for (A, B) {
+    fn stable_hash_into<H: Hasher>(&self, hasher: &mut H) {
+        self.0.stable_hash_into(hasher);
+        self.1.stable_hash_into(hasher);
+    }
+}
+
+/// Unlike std's `DefaultHasher`, comes out the same
+/// no matter which machine or Rust version built the node.
+pub(crate) fn stable_hash<K: StableHash + ?Sized>(key: &K) -> u64 {
+    let mut hasher = Fnv1a::default();
+    key.stable_hash_into(&mut hasher);
+    // FNV alone clusters similar keys like "n1" and "n2".
+    SplitMix64::new(hasher.finish()).next_u64()
+}
+
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// A fast (FxHash-style) hasher for integer keys, like the message ids
+/// we check every broadcast against. Much quicker than std's SipHash, but
+/// easy to flood with collisions on purpose, which Maelstrom never does.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FxHasher(u64);
+
+/// Builds [FxHasher]s, e.g. for [crate::SeenSet]'s hasher.
+pub type FxBuildHasher = BuildHasherDefault<FxHasher>;
+
+impl FxHasher {
+    fn add(&mut self, word: u64) {
+        self.0 = (self.0.rotate_left(5) ^ word).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+}
+
+impl Hasher for FxHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.add(u64::from_le_bytes(word));
+        }
+    }
+
+    fn write_u8(&mut self, n: u8) {
+        self.add(n as u64);
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.add(n as u64);
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.add(n);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.add(n as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nodes(count: usize) -> Vec<NodeId> {
+        (1..=count).map(|n| NodeId::from(format!("n{n}"))).collect()
+    }
+
+    #[test]
+    fn adding_a_node_moves_about_its_share_of_keys_to_it() {
+        let mut hash = ConsistentHash::new(&nodes(4));
+        let moved = hash.rebalance(&nodes(5), 0..10_000);
+
+        // A fifth of them, give or take.
+        assert!((1_500..=2_500).contains(&moved.len()), "{}", moved.len());
+        assert!(moved.iter().all(|handoff| handoff.to.as_str() == "n5"));
+        assert!(moved.iter().all(|handoff| handoff.from.is_some()));
+        for key in 0..10_000 {
+            let moved_here = moved.iter().any(|handoff| handoff.key == key);
+            assert_eq!(hash.owner_of(&key).unwrap().as_str() == "n5", moved_here);
+        }
+    }
+
+    #[test]
+    fn hashes_are_the_same_everywhere() {
+        // Whatever the platform, these never change.
+        assert_eq!(stable_hash("n1"), 0x104d_4705_3e09_0608);
+        assert_eq!(stable_hash(&7usize), 0xfe79_3e3c_e142_343a);
+        assert_eq!(stable_hash(&("n1", 3usize)), 0xef5e_d9fa_6a5b_8a85);
+        // Nor does the width an integer happens to have.
+        assert_eq!(stable_hash(&7u32), stable_hash(&7u64));
+        assert_eq!(stable_hash(&NodeId::from("n1")), stable_hash("n1"));
+        assert_ne!(stable_hash(&("n1", "2")), stable_hash(&("n12", "")));
+    }
+
+    #[test]
+    fn owners_are_stable_and_balanced_over_100_keys() {
+        let node_ids = nodes(5);
+        let hash = ConsistentHash::new(&node_ids);
+        let mut reversed = node_ids.clone();
+        reversed.reverse();
+        let same = ConsistentHash::new(&reversed);
+
+        let mut counts = std::collections::HashMap::new();
+        for key in 0..100 {
+            let key = format!("key-{key}");
+            let owner = hash.owner_of(&key).unwrap();
+            assert_eq!(same.owner_of(&key), Some(owner), "{key}");
+            assert_eq!(hash.owner_of(&key), Some(owner), "{key}");
+            *counts.entry(owner.clone()).or_insert(0) += 1;
+        }
+        assert_eq!(counts.len(), 5);
+        assert!(
+            counts.values().all(|&count| (8..=35).contains(&count)),
+            "{counts:?}"
+        );
+    }
+
+    #[test]
+    fn fx_hashes_are_deterministic() {
+        use std::hash::BuildHasher;
+
+        let hash = |value: usize| FxBuildHasher::default().hash_one(value);
+        assert_eq!(hash(7), hash(7));
+        assert_eq!(hash(7), 7u64.wrapping_mul(0x517c_c1b7_2722_0a95));
+        assert_ne!(hash(7), hash(8));
+        assert_eq!(
+            FxBuildHasher::default().hash_one("n1"),
+            FxBuildHasher::default().hash_one("n1")
+        );
+    }
+
+    #[test]
+    fn fx_set_holds_the_same_as_a_std_one() {
+        use crate::SeenSet;
+        use std::collections::hash_map::RandomState;
+
+        let mut fx: SeenSet<usize, FxBuildHasher> = SeenSet::default();
+        let mut std: SeenSet<usize, RandomState> = SeenSet::default();
+        for value in (0..10_000).map(|n| n * 7 % 5_000) {
+            assert_eq!(fx.insert(value), std.insert(value), "{value}");
+        }
+        assert_eq!(fx.len(), 5_000);
+        assert!((0..5_000).all(|value| fx.contains(&value) && std.contains(&value)));
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore = "timing-dependent benchmark"]
+    fn fx_inserts_faster_than_std() {
+        use std::{
+            collections::{hash_map::RandomState, HashSet},
+            hash::BuildHasher,
+            hint::black_box,
+            time::{Duration, Instant},
+        };
+
+        fn insert_all<S: BuildHasher + Default>() -> Duration {
+            let started = Instant::now();
+            let mut seen: HashSet<usize, S> = HashSet::default();
+            for value in 0..1_000_000 {
+                black_box(seen.insert(value));
+            }
+            started.elapsed()
+        }
+
+        let (fx, std) = (insert_all::<FxBuildHasher>(), insert_all::<RandomState>());
+        println!("fx: {fx:?}, std: {std:?}");
+        assert!(fx < std);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::Envelope;
+use serde_json::Value;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// Which way an envelope went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+/// An envelope that came in or went out, as it looked on the wire.
+#[derive(Debug, Clone)]
+pub struct Recorded {
+    pub direction: Direction,
+    pub envelope: Envelope<Value>,
+}
+
+/// The last few envelopes we received or sent, oldest first,
+/// e.g. to check whether we ever sent a `sync` to `n3`.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    capacity: usize,
+    records: VecDeque<Recorded>,
+}
+
+impl History {
+    /// Keep (at most) the newest `capacity` envelopes.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn record(&mut self, direction: Direction, envelope: Envelope<Value>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(Recorded {
+            direction,
+            envelope,
+        });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Recorded> {
+        self.records.iter()
+    }
+
+    pub fn received(&self) -> impl Iterator<Item = &Envelope<Value>> {
+        self.with_direction(Direction::Received)
+    }
+
+    pub fn sent(&self) -> impl Iterator<Item = &Envelope<Value>> {
+        self.with_direction(Direction::Sent)
+    }
+
+    fn with_direction(&self, direction: Direction) -> impl Iterator<Item = &Envelope<Value>> {
+        self.records
+            .iter()
+            .filter(move |record| record.direction == direction)
+            .map(|record| &record.envelope)
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+/// Zero means we aren't recording anything,
+/// so we don't even have to take the lock.
+static CAPACITY: AtomicUsize = AtomicUsize::new(0);
+
+static HISTORY: Mutex<History> = Mutex::new(History {
+    capacity: 0,
+    records: VecDeque::new(),
+});
+
+pub(crate) fn set_capacity(capacity: usize) {
+    CAPACITY.store(capacity, Ordering::SeqCst);
+    let mut history = HISTORY.lock().unwrap();
+    let records = std::mem::take(&mut history.records);
+    *history = History::with_capacity(capacity);
+    for record in records {
+        history.record(record.direction, record.envelope);
+    }
+}
+
+/// Remember a line that went over the wire, if we're recording at all.
+pub(crate) fn record_line(direction: Direction, line: &[u8]) {
+    if CAPACITY.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    if let Ok(envelope) = serde_json::from_slice(line) {
+        HISTORY.lock().unwrap().record(direction, envelope);
+    }
+}
+
+/// What the whole process has received and sent lately.
+/// Empty unless [crate::RuntimeConfig::history_capacity] is set.
+pub fn history() -> History {
+    HISTORY.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn envelope(n: usize) -> Envelope<Value> {
+        Envelope::new("n1", "n2", None, json!({"type": "sync", "n": n}))
+    }
+
+    fn numbers<'a>(envelopes: impl Iterator<Item = &'a Envelope<Value>>) -> Vec<u64> {
+        envelopes
+            .map(|envelope| envelope.message()["n"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn only_the_newest_are_kept() {
+        let mut history = History::with_capacity(3);
+        for n in 0..5 {
+            let direction = if n % 2 == 0 {
+                Direction::Sent
+            } else {
+                Direction::Received
+            };
+            history.record(direction, envelope(n));
+        }
+        assert_eq!(history.len(), 3);
+        let all = numbers(history.iter().map(|record| &record.envelope));
+        assert_eq!(all, [2, 3, 4]);
+        assert_eq!(numbers(history.sent()), [2, 4]);
+        assert_eq!(numbers(history.received()), [3]);
+    }
+
+    #[test]
+    fn no_capacity_keeps_nothing() {
+        let mut history = History::default();
+        history.record(Direction::Sent, envelope(0));
+        assert!(history.is_empty());
+    }
+}
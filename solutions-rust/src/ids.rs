@@ -0,0 +1,339 @@
+use crate::NodeId;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// What the ids an [IdAllocator] hands out look like.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IdFormat {
+    /// `"{node_id}-{counter}"`, e.g. `"n1-7"`.
+    #[default]
+    NodeCounter,
+    /// A UUID (version 8, i.e. laid out by us): the node's position among
+    /// all the nodes in the top half, the counter in the bottom half.
+    Uuid,
+    /// A plain number: the node's position among all the nodes in the top
+    /// `node_bits`, the counter in the bottom `seq_bits`. The defaults keep
+    /// ids under 2^53, so they survive being read as a javascript number.
+    Numeric { node_bits: u32, seq_bits: u32 },
+}
+
+impl IdFormat {
+    pub const NUMERIC: IdFormat = IdFormat::Numeric {
+        node_bits: 10,
+        seq_bits: 43,
+    };
+}
+
+impl FromStr for IdFormat {
+    type Err = String;
+
+    /// `node-counter`, `uuid`, `numeric`, or `numeric:{node_bits}:{seq_bits}`,
+    /// with at most 64 bits between the two.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split(':').collect::<Vec<_>>()[..] {
+            ["node-counter"] => Ok(IdFormat::NodeCounter),
+            ["uuid"] => Ok(IdFormat::Uuid),
+            ["numeric"] => Ok(IdFormat::NUMERIC),
+            ["numeric", node_bits, seq_bits] => {
+                let node_bits: u32 = node_bits
+                    .parse()
+                    .map_err(|err| format!("Bad node_bits {node_bits}: {err}"))?;
+                let seq_bits: u32 = seq_bits
+                    .parse()
+                    .map_err(|err| format!("Bad seq_bits {seq_bits}: {err}"))?;
+                if !fits_in_u64(node_bits, seq_bits) {
+                    return Err(IdAllocatorError::TooManyBits {
+                        node_bits,
+                        seq_bits,
+                    }
+                    .to_string());
+                }
+                Ok(IdFormat::Numeric {
+                    node_bits,
+                    seq_bits,
+                })
+            }
+            _ => Err(format!("Unknown id format: {s}")),
+        }
+    }
+}
+
+/// Whether [IdFormat::Numeric] ids with these many bits fit in a u64.
+fn fits_in_u64(node_bits: u32, seq_bits: u32) -> bool {
+    node_bits
+        .checked_add(seq_bits)
+        .is_some_and(|bits| bits <= 64)
+}
+
+/// An id from an [IdAllocator], which goes out on the wire
+/// as a string or a number, depending on the format.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum UniqueId {
+    Text(String),
+    Number(u64),
+}
+
+impl fmt::Display for UniqueId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UniqueId::Text(id) => f.write_str(id),
+            UniqueId::Number(id) => write!(f, "{id}"),
+        }
+    }
+}
+
+/// Why an [IdAllocator] couldn't promise that its ids are unique.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdAllocatorError {
+    /// The format wants a position among all the nodes, but we aren't one of them.
+    NotInCluster(NodeId),
+    /// [IdFormat::Numeric] ids don't fit in a u64.
+    TooManyBits { node_bits: u32, seq_bits: u32 },
+    /// [IdFormat::Numeric] ids don't have room for our position.
+    TooFewNodeBits { node_bits: u32, node_index: u64 },
+}
+
+impl fmt::Display for IdAllocatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotInCluster(node_id) => {
+                write!(f, "Node {node_id} isn't one of the nodes it was given")
+            }
+            Self::TooManyBits {
+                node_bits,
+                seq_bits,
+            } => write!(
+                f,
+                "Numeric ids need node_bits + seq_bits <= 64, got {node_bits} + {seq_bits}"
+            ),
+            Self::TooFewNodeBits {
+                node_bits,
+                node_index,
+            } => write!(
+                f,
+                "{node_bits} node bits aren't enough for node #{node_index}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for IdAllocatorError {}
+
+/// Hands out ids that are unique across the whole cluster without
+/// any coordination, as every format mixes in something only this
+/// node has (its id, or its position among all the nodes) next to
+/// a counter only this node bumps.
+#[derive(Debug, Clone)]
+pub struct IdAllocator {
+    format: IdFormat,
+    node_id: NodeId,
+    node_index: u64,
+    next: u64,
+}
+
+impl IdAllocator {
+    /// Fails if `format` can't fit every node in `node_ids`, or needs
+    /// our position among them and we aren't there, as then we
+    /// couldn't promise the ids are unique.
+    pub fn new(
+        format: IdFormat,
+        node_id: &NodeId,
+        node_ids: &[NodeId],
+    ) -> Result<Self, IdAllocatorError> {
+        let mut all_nodes = node_ids.to_vec();
+        all_nodes.sort();
+        let position = all_nodes.iter().position(|other| other == node_id);
+        let node_index = match (format, position) {
+            (_, Some(position)) => position as u64,
+            (IdFormat::NodeCounter, None) => 0,
+            (_, None) => return Err(IdAllocatorError::NotInCluster(node_id.clone())),
+        };
+
+        if let IdFormat::Numeric {
+            node_bits,
+            seq_bits,
+        } = format
+        {
+            if !fits_in_u64(node_bits, seq_bits) {
+                return Err(IdAllocatorError::TooManyBits {
+                    node_bits,
+                    seq_bits,
+                });
+            }
+            if node_index.checked_shr(node_bits).unwrap_or(0) != 0 {
+                return Err(IdAllocatorError::TooFewNodeBits {
+                    node_bits,
+                    node_index,
+                });
+            }
+        }
+
+        Ok(Self {
+            format,
+            node_id: node_id.clone(),
+            node_index,
+            next: 0,
+        })
+    }
+
+    pub fn format(&self) -> IdFormat {
+        self.format
+    }
+
+    /// The next id, or `None` if we've used up every id the format leaves
+    /// us (2^62 of them with [IdFormat::Uuid], fewer with [IdFormat::Numeric]).
+    pub fn next_id(&mut self) -> Option<UniqueId> {
+        let seq = self.next;
+        let id = match self.format {
+            IdFormat::NodeCounter => UniqueId::Text(format!("{}-{seq}", self.node_id)),
+            IdFormat::Uuid => {
+                if seq >> 62 != 0 {
+                    return None;
+                }
+                // Version 8 in the top half, RFC 4122's variant in the bottom half.
+                let high = (self.node_index << 16) | 0x8000;
+                let low = seq | 0x8000_0000_0000_0000;
+                UniqueId::Text(format!(
+                    "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+                    high >> 32,
+                    (high >> 16) & 0xffff,
+                    high & 0xffff,
+                    low >> 48,
+                    low & 0xffff_ffff_ffff
+                ))
+            }
+            IdFormat::Numeric { seq_bits, .. } => {
+                if seq.checked_shr(seq_bits).unwrap_or(0) != 0 {
+                    return None;
+                }
+                UniqueId::Number(self.node_index.checked_shl(seq_bits).unwrap_or(0) | seq)
+            }
+        };
+        self.next += 1;
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn nodes(count: usize) -> Vec<NodeId> {
+        (1..=count).map(|n| NodeId::from(format!("n{n}"))).collect()
+    }
+
+    /// A thousand ids from each of `count` nodes.
+    fn ids_from(format: IdFormat, count: usize) -> Vec<UniqueId> {
+        let node_ids = nodes(count);
+        node_ids
+            .iter()
+            .flat_map(|node_id| {
+                let mut ids = IdAllocator::new(format, node_id, &node_ids).unwrap();
+                (0..1000).map(move |_| ids.next_id().unwrap())
+            })
+            .collect()
+    }
+
+    fn is_uuid_v8(id: &str) -> bool {
+        let groups: Vec<&str> = id.split('-').collect();
+        groups.iter().map(|group| group.len()).eq([8, 4, 4, 4, 12])
+            && id.chars().all(|c| c == '-' || c.is_ascii_hexdigit())
+            && groups[2].starts_with('8')
+            && groups[3].starts_with(['8', '9', 'a', 'b'])
+    }
+
+    #[test]
+    fn ids_are_unique_across_nodes_in_every_format() {
+        for format in [IdFormat::NodeCounter, IdFormat::Uuid, IdFormat::NUMERIC] {
+            let ids = ids_from(format, 25);
+            assert_eq!(
+                ids.iter().collect::<HashSet<_>>().len(),
+                ids.len(),
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn ids_look_like_their_format() {
+        for id in ids_from(IdFormat::NodeCounter, 3) {
+            let UniqueId::Text(id) = id else {
+                panic!("{id:?} isn't text");
+            };
+            let (node, counter) = id.split_once('-').unwrap();
+            assert!(
+                node.starts_with('n') && counter.parse::<u64>().is_ok(),
+                "{id}"
+            );
+        }
+        for id in ids_from(IdFormat::Uuid, 3) {
+            assert!(
+                matches!(&id, UniqueId::Text(id) if is_uuid_v8(id)),
+                "{id:?}"
+            );
+        }
+        for id in ids_from(IdFormat::NUMERIC, 3) {
+            assert!(matches!(id, UniqueId::Number(id) if id < 1 << 53), "{id:?}");
+        }
+    }
+
+    #[test]
+    fn formats_that_cant_promise_uniqueness_are_refused() {
+        let node_ids = nodes(5);
+        let numeric = |node_bits, seq_bits| IdFormat::Numeric {
+            node_bits,
+            seq_bits,
+        };
+        assert_eq!(
+            IdAllocator::new(numeric(40, 40), &node_ids[0], &node_ids).unwrap_err(),
+            IdAllocatorError::TooManyBits {
+                node_bits: 40,
+                seq_bits: 40
+            }
+        );
+        assert_eq!(
+            IdAllocator::new(numeric(2, 8), &node_ids[4], &node_ids).unwrap_err(),
+            IdAllocatorError::TooFewNodeBits {
+                node_bits: 2,
+                node_index: 4
+            }
+        );
+        let stranger = NodeId::from("n9");
+        assert!(IdAllocator::new(IdFormat::Uuid, &stranger, &node_ids).is_err());
+        assert!(IdAllocator::new(IdFormat::NodeCounter, &stranger, &node_ids).is_ok());
+
+        let mut ids = IdAllocator::new(numeric(3, 2), &node_ids[0], &node_ids).unwrap();
+        assert_eq!((0..5).filter_map(|_| ids.next_id()).count(), 4);
+    }
+
+    #[test]
+    fn formats_parse_or_say_why_not() {
+        assert_eq!("uuid".parse(), Ok(IdFormat::Uuid));
+        assert_eq!("numeric".parse(), Ok(IdFormat::NUMERIC));
+        assert_eq!(
+            "numeric:8:56".parse(),
+            Ok(IdFormat::Numeric {
+                node_bits: 8,
+                seq_bits: 56
+            })
+        );
+        for bad in [
+            "numeric:8:57",
+            "numeric:4294967295:1",
+            "numeric:x:1",
+            "uuid:4",
+        ] {
+            assert!(bad.parse::<IdFormat>().is_err(), "{bad}");
+        }
+
+        // Nor does building one by hand get around the check.
+        let node_ids = nodes(1);
+        let format = IdFormat::Numeric {
+            node_bits: u32::MAX,
+            seq_bits: 1,
+        };
+        assert!(IdAllocator::new(format, &node_ids[0], &node_ids).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+/// How many pending items make us busy, unless told otherwise.
+const BUSY_AT: usize = 16;
+
+/// A flush period that paces itself: it halves (down to `min`) while
+/// there's a lot waiting to go out, and doubles (up to `max`) while
+/// there's nothing at all, so we don't send empty syncs when idle, or
+/// let a backlog sit around for a whole period when busy.
+///
+/// Feed it to [crate::Events::set_tick_interval] on every tick.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdaptiveInterval {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+    busy_at: usize,
+}
+
+impl AdaptiveInterval {
+    /// Starts out idle, i.e. at `max`.
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max,
+            current: max,
+            busy_at: BUSY_AT,
+        }
+    }
+
+    /// How many pending items it takes for us to speed up.
+    pub fn with_busy_at(mut self, pending: usize) -> Self {
+        self.busy_at = pending.max(1);
+        self
+    }
+
+    pub fn current(&self) -> Duration {
+        self.current
+    }
+
+    /// Adjust to having `pending` items waiting to go out, and
+    /// hand back how long to wait until the next flush.
+    pub fn observe(&mut self, pending: usize) -> Duration {
+        if pending >= self.busy_at {
+            self.current = (self.current / 2).max(self.min);
+        } else if pending == 0 {
+            self.current = (self.current * 2).min(self.max);
+        }
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Clock, ManualClock};
+
+    const MIN: Duration = Duration::from_millis(50);
+    const MAX: Duration = Duration::from_millis(400);
+
+    /// Flush once for every entry in `pending` (that many items waiting
+    /// each time), a whole interval apart, and say when each flush was.
+    fn flushes(
+        interval: &mut AdaptiveInterval,
+        clock: &ManualClock,
+        pending: &[usize],
+    ) -> Vec<u128> {
+        let started = clock.now();
+        pending
+            .iter()
+            .map(|&pending| {
+                clock.advance(interval.observe(pending));
+                (clock.now() - started).as_millis()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn speeds_up_on_a_burst_and_backs_off_when_idle() {
+        let clock = ManualClock::new();
+        let mut interval = AdaptiveInterval::new(MIN, MAX).with_busy_at(10);
+        assert_eq!(interval.current(), MAX);
+
+        // A burst: halving down to the min, and no further.
+        let at = flushes(&mut interval, &clock, &[100, 100, 50, 10, 10]);
+        assert_eq!(at, [200, 300, 350, 400, 450]);
+        assert_eq!(interval.current(), MIN);
+
+        // A trickle isn't busy, nor idle, so we stay put.
+        let at = flushes(&mut interval, &clock, &[3, 3]);
+        assert_eq!(at, [50, 100]);
+
+        // Idle: doubling back up to the max, and no further.
+        let at = flushes(&mut interval, &clock, &[0, 0, 0, 0, 0]);
+        assert_eq!(at, [100, 300, 700, 1100, 1500]);
+        assert_eq!(interval.current(), MAX);
+    }
+}
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// An offset per log key, as in `poll`, `commit_offsets`
+/// and `list_committed_offsets_ok`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct OffsetMap(HashMap<String, usize>);
+
+impl OffsetMap {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with(mut self, key: &str, offset: usize) -> Self {
+        self.insert(key, offset);
+        self
+    }
+
+    pub fn insert(&mut self, key: &str, offset: usize) {
+        self.0.insert(key.to_owned(), offset);
+    }
+
+    pub fn get(&self, key: &str) -> Option<usize> {
+        self.0.get(key).copied()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.0.iter().map(|(key, offset)| (key.as_str(), *offset))
+    }
+}
+
+impl FromIterator<(String, usize)> for OffsetMap {
+    fn from_iter<I: IntoIterator<Item = (String, usize)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+/// The `[offset, message]` pairs per log key in a `poll_ok`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PollResult(HashMap<String, Vec<[usize; 2]>>);
+
+impl PollResult {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with(mut self, key: &str, messages: Vec<[usize; 2]>) -> Self {
+        self.insert(key, messages);
+        self
+    }
+
+    pub fn insert(&mut self, key: &str, messages: Vec<[usize; 2]>) {
+        self.0.insert(key.to_owned(), messages);
+    }
+
+    pub fn get(&self, key: &str) -> Option<&[[usize; 2]]> {
+        self.0.get(key).map(Vec::as_slice)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &[[usize; 2]])> {
+        self.0
+            .iter()
+            .map(|(key, messages)| (key.as_str(), messages.as_slice()))
+    }
+}
+
+impl FromIterator<(String, Vec<[usize; 2]>)> for PollResult {
+    fn from_iter<I: IntoIterator<Item = (String, Vec<[usize; 2]>)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
@@ -0,0 +1,304 @@
+use crate::{Clock, Envelope, NodeId, SystemClock};
+use serde::{Deserialize, Serialize};
+use std::{
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
+
+/// What a [Lease] keeps in the kv store: who holds it, and how many times
+/// it's been taken or renewed, so that every renewal is a different value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaseToken {
+    pub holder: NodeId,
+    pub term: u64,
+}
+
+/// The messages a [Lease] has to send,
+/// built out of whatever message type the node uses.
+pub trait LeaseMessages: Serialize {
+    fn read(key: String) -> Self;
+    /// A cas of `key` from `from` to `to`, or, without a `from`,
+    /// one that creates the key (i.e. with `create_if_not_exists`).
+    fn cas(key: String, from: Option<LeaseToken>, to: LeaseToken) -> Self;
+}
+
+/// What's in the kv store under our key, as far as we know.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Seen {
+    Vacant,
+    /// Along with when we first saw it there.
+    Token(LeaseToken, Instant),
+}
+
+/// A lock over `lin-kv` that's only ever held for `ttl` at a time,
+/// unless it's renewed, e.g. for a leader that steps down on its own
+/// if it gets cut off from the rest of the cluster.
+///
+/// Nodes don't share a clock, so nobody writes down when a lease runs out.
+/// Instead, a lease counts as expired once its token has sat unchanged in
+/// the store for a whole `ttl` (by our own clock), at which point anybody
+/// can cas it over to themselves. The holder counts its `ttl` from when it
+/// sent its cas, so it always lets go first.
+#[derive(Debug)]
+pub struct Lease<M, C = SystemClock> {
+    key: String,
+    ttl: Duration,
+    id: NodeId,
+    clock: C,
+    seen: Option<Seen>,
+    /// Until when we hold it, if we do.
+    held_until: Option<Instant>,
+    /// The cas we're waiting on, as (msg_id, to, sent_at).
+    in_flight: Option<(usize, LeaseToken, Instant)>,
+    /// The read we're waiting on.
+    reading: Option<usize>,
+    message: PhantomData<fn() -> M>,
+}
+
+impl<M: LeaseMessages> Lease<M> {
+    pub fn new(id: &NodeId, key: &str, ttl: Duration) -> Self {
+        Self::with_clock(id, key, ttl, SystemClock)
+    }
+}
+
+impl<M: LeaseMessages, C: Clock> Lease<M, C> {
+    /// Where the lease lives.
+    pub const KV: &'static str = "lin-kv";
+
+    pub fn with_clock(id: &NodeId, key: &str, ttl: Duration, clock: C) -> Self {
+        Self {
+            key: key.to_owned(),
+            ttl,
+            id: id.clone(),
+            clock,
+            seen: None,
+            held_until: None,
+            in_flight: None,
+            reading: None,
+            message: PhantomData,
+        }
+    }
+
+    /// Whether we hold the lease right now.
+    pub fn is_held(&self) -> bool {
+        self.held_until
+            .is_some_and(|held_until| self.clock.now() < held_until)
+    }
+
+    /// Try to take the lease, if it's free (or expired). Call it again (e.g.
+    /// on every tick) until [Lease::is_held]; we may have to find out
+    /// what's in the store, or wait for it to expire, first.
+    pub fn acquire(&mut self) {
+        if self.is_held() || self.in_flight.is_some() || self.reading.is_some() {
+            return;
+        }
+        let now = self.clock.now();
+        match &self.seen {
+            None => self.read(),
+            Some(Seen::Vacant) => self.cas(None, 1),
+            Some(Seen::Token(token, since)) => {
+                if token.holder == self.id || now.saturating_duration_since(*since) >= self.ttl {
+                    self.cas(Some(token.clone()), token.term + 1);
+                }
+            }
+        }
+    }
+
+    /// Hold on to the lease for another `ttl`, if we still have it.
+    pub fn renew(&mut self) {
+        if self.is_held() && self.in_flight.is_none() {
+            if let Some(Seen::Token(token, _)) = &self.seen {
+                self.cas(Some(token.clone()), token.term + 1);
+            }
+        }
+    }
+
+    /// The store took our cas. Returns whether it was ours.
+    pub fn cas_ok(&mut self, in_reply_to: Option<usize>) -> bool {
+        match self.in_flight.take() {
+            Some((msg_id, token, sent_at)) if Some(msg_id) == in_reply_to => {
+                self.seen = Some(Seen::Token(token, sent_at));
+                self.held_until = Some(sent_at + self.ttl);
+                true
+            }
+            in_flight => {
+                self.in_flight = in_flight;
+                false
+            }
+        }
+    }
+
+    /// The store turned down our cas (or our read, since the key doesn't
+    /// exist). Returns whether it was one of ours. Either way, we're no
+    /// longer sure what's in there, so the next [Lease::acquire] reads it.
+    pub fn error(&mut self, in_reply_to: Option<usize>, key_does_not_exist: bool) -> bool {
+        if in_reply_to.is_some() && in_reply_to == self.reading {
+            self.reading = None;
+            self.seen = key_does_not_exist.then_some(Seen::Vacant);
+            return true;
+        }
+        match self.in_flight {
+            Some((msg_id, ..)) if Some(msg_id) == in_reply_to => {
+                self.in_flight = None;
+                self.seen = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The store told us who holds the lease. Returns whether it was our read.
+    pub fn read_ok(&mut self, in_reply_to: Option<usize>, token: LeaseToken) -> bool {
+        if in_reply_to.is_none() || in_reply_to != self.reading {
+            return false;
+        }
+        self.reading = None;
+        // Only a change restarts the countdown.
+        if !matches!(&self.seen, Some(Seen::Token(seen, _)) if *seen == token) {
+            self.seen = Some(Seen::Token(token, self.clock.now()));
+        }
+        true
+    }
+
+    fn read(&mut self) {
+        let envelope = Envelope::new(&self.id, Self::KV, None, M::read(self.key.clone()));
+        self.reading = envelope.msg_id();
+        envelope.send();
+    }
+
+    fn cas(&mut self, from: Option<LeaseToken>, term: u64) {
+        let to = LeaseToken {
+            holder: self.id.clone(),
+            term,
+        };
+        let envelope = Envelope::new(
+            &self.id,
+            Self::KV,
+            None,
+            M::cas(self.key.clone(), from, to.clone()),
+        );
+        self.in_flight = Some((envelope.msg_id().unwrap(), to, self.clock.now()));
+        envelope.send();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capture::capturing, drain_outbound, ManualClock};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Message {
+        Read {
+            key: String,
+        },
+        Cas {
+            key: String,
+            from: Option<LeaseToken>,
+            to: LeaseToken,
+        },
+    }
+
+    impl LeaseMessages for Message {
+        fn read(key: String) -> Self {
+            Message::Read { key }
+        }
+
+        fn cas(key: String, from: Option<LeaseToken>, to: LeaseToken) -> Self {
+            Message::Cas { key, from, to }
+        }
+    }
+
+    const TTL: Duration = Duration::from_secs(1);
+
+    fn lease(id: &str, clock: &ManualClock) -> Lease<Message, ManualClock> {
+        Lease::with_clock(&NodeId::from(id), "leader", TTL, clock.clone())
+    }
+
+    /// Play `lin-kv` holding `stored` for whatever `lease` sent it.
+    fn answer(stored: &mut Option<LeaseToken>, lease: &mut Lease<Message, ManualClock>) {
+        for envelope in drain_outbound() {
+            let envelope: Envelope<Message> =
+                serde_json::from_value(serde_json::to_value(envelope).unwrap()).unwrap();
+            assert_eq!(envelope.dest, Lease::<Message>::KV);
+            let msg_id = envelope.msg_id();
+            match envelope.message() {
+                Message::Read { .. } => match stored {
+                    Some(token) => assert!(lease.read_ok(msg_id, token.clone())),
+                    None => assert!(lease.error(msg_id, true)),
+                },
+                Message::Cas { from, to, .. } => {
+                    if stored == from {
+                        *stored = Some(to.clone());
+                        assert!(lease.cas_ok(msg_id));
+                    } else {
+                        assert!(lease.error(msg_id, false));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Keep calling [Lease::acquire] for as long as it has something to ask.
+    fn try_acquire(stored: &mut Option<LeaseToken>, lease: &mut Lease<Message, ManualClock>) {
+        for _ in 0..4 {
+            lease.acquire();
+            answer(stored, lease);
+        }
+    }
+
+    #[test]
+    fn expired_lease_goes_to_the_next_node() {
+        let _capture = capturing();
+        let clock = ManualClock::new();
+        let mut stored = None;
+        let (mut n1, mut n2) = (lease("n1", &clock), lease("n2", &clock));
+
+        try_acquire(&mut stored, &mut n1);
+        assert!(n1.is_held());
+        try_acquire(&mut stored, &mut n2);
+        assert!(!n2.is_held(), "n1 still holds it");
+
+        clock.advance(TTL / 2);
+        try_acquire(&mut stored, &mut n2);
+        assert!(n1.is_held() && !n2.is_held());
+
+        clock.advance(TTL / 2);
+        assert!(!n1.is_held(), "n1 lets go first");
+        try_acquire(&mut stored, &mut n2);
+        assert!(n2.is_held());
+        assert_eq!(
+            stored,
+            Some(LeaseToken {
+                holder: NodeId::from("n2"),
+                term: 2
+            })
+        );
+    }
+
+    #[test]
+    fn renewed_lease_is_not_taken() {
+        let _capture = capturing();
+        let clock = ManualClock::new();
+        let mut stored = None;
+        let (mut n1, mut n2) = (lease("n1", &clock), lease("n2", &clock));
+
+        try_acquire(&mut stored, &mut n1);
+        try_acquire(&mut stored, &mut n2);
+
+        clock.advance(TTL / 2);
+        n1.renew();
+        answer(&mut stored, &mut n1);
+
+        clock.advance(TTL / 2);
+        // n2 only finds out about the renewal when its cas fails,
+        // and then has to wait out a whole ttl from there.
+        try_acquire(&mut stored, &mut n2);
+        assert!(n1.is_held() && !n2.is_held());
+
+        clock.advance(TTL);
+        try_acquire(&mut stored, &mut n2);
+        assert!(n2.is_held());
+    }
+}
@@ -1,14 +1,238 @@
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Serialize};
 use std::fmt::Debug;
-use std::io;
+use std::io::{self, BufRead};
 use std::sync::mpsc::Sender;
 
+mod address;
+mod batch;
+mod bounded;
+mod broadcast;
+mod capture;
+mod clock;
+mod config;
+mod context;
+mod cost;
+mod counter;
+mod crdt;
+mod dense;
+mod durable;
 mod envelope;
+mod error;
+mod events;
+#[cfg(any(test, feature = "test-util"))]
+mod fixtures;
+mod framing;
+mod hashing;
+mod history;
+mod ids;
+mod interval;
+mod kafka;
+mod lease;
+mod metrics;
+mod middleware;
+mod pairing;
+mod peer_queue;
+mod quorum;
+mod raw;
+mod relay;
+mod retry;
+mod rng;
+mod rpc;
+mod seen;
+mod sequence;
+mod sorted;
+mod stats;
+mod tap;
+mod topology;
+mod txn;
+pub use address::*;
+pub use batch::*;
+pub use bounded::*;
+pub use broadcast::*;
+pub use capture::*;
+pub use clock::*;
+pub use config::*;
+pub use context::*;
+pub use cost::*;
+pub use counter::*;
+pub use crdt::*;
+pub use dense::*;
+pub use durable::*;
 pub use envelope::*;
+pub use error::*;
+pub use events::*;
+#[cfg(any(test, feature = "test-util"))]
+pub use fixtures::*;
+pub use framing::*;
+pub use hashing::*;
+pub use history::*;
+pub use ids::*;
+pub use interval::*;
+pub use kafka::*;
+pub use lease::*;
+pub use metrics::*;
+pub use middleware::*;
+pub use pairing::*;
+pub use peer_queue::*;
+pub use quorum::*;
+pub use raw::*;
+pub use relay::*;
+pub use retry::*;
+pub use rpc::*;
+pub use seen::*;
+pub use sequence::*;
+pub use sorted::*;
+pub use stats::*;
+pub use topology::*;
+pub use txn::*;
 
-pub fn read_stdin<B: Debug + DeserializeOwned>(incoming_messages_tx: Sender<Envelope<B>>) {
-    for line in io::stdin().lines().map(Result::unwrap) {
-        let decoded = serde_json::from_str(&line).unwrap();
-        incoming_messages_tx.send(decoded).unwrap();
+pub fn read_stdin<B: Debug + DeserializeOwned>(
+    incoming_messages_tx: Sender<Envelope<B>>,
+) -> Result<(), RuntimeError> {
+    read_stdin_through(incoming_messages_tx, InboundChain::new())
+}
+
+/// Like [read_stdin], but only forwards the envelopes
+/// that make it through the given middleware chain.
+pub fn read_stdin_through<B: Debug + DeserializeOwned>(
+    incoming_messages_tx: Sender<Envelope<B>>,
+    mut inbound: InboundChain<B>,
+) -> Result<(), RuntimeError> {
+    for_each_stdin_line(|line| {
+        let decoded = serde_json::from_str(line)?;
+        if inbound.process(&decoded) == Flow::Continue {
+            incoming_messages_tx
+                .send(decoded)
+                .map_err(|_| RuntimeError::ChannelClosed)?;
+        }
+        Ok(())
+    })
+}
+
+/// Like [read_stdin], but hands over whatever couldn't be decoded
+/// as a [MalformedRequest] instead of panicking, so that the node can
+/// answer with an `error`. With [Strictness::Strict], bodies with fields
+/// the message type doesn't know about are malformed too.
+pub fn read_stdin_checked<B: Debug + DeserializeOwned + Serialize>(
+    incoming_messages_tx: Sender<Result<Envelope<B>, MalformedRequest>>,
+    strictness: Strictness,
+) -> Result<(), RuntimeError> {
+    for_each_stdin_line(|line| {
+        incoming_messages_tx
+            .send(Envelope::parse(line, strictness))
+            .map_err(|_| RuntimeError::ChannelClosed)
+    })
+}
+
+/// [for_each_line] over stdin, keeping count
+/// of what came in, with the `stats` feature on.
+fn for_each_stdin_line<F>(mut on_line: F) -> Result<(), RuntimeError>
+where
+    F: FnMut(&str) -> Result<(), RuntimeError>,
+{
+    #[cfg(feature = "stats")]
+    Statistics::global().start();
+
+    let result = for_each_frame(io::stdin().lock(), &config::framing(), |line| {
+        #[cfg(feature = "stats")]
+        Statistics::global().record_inbound();
+        history::record_line(Direction::Received, line.as_bytes());
+        tap::record_line(Direction::Received, line.as_bytes());
+        on_line(line)
+    });
+
+    #[cfg(feature = "stats")]
+    eprintln!("{}", Statistics::global());
+
+    result
+}
+
+/// Feed every line from the reader to `on_line`, until we run out of
+/// them, the reader fails for good, or `on_line` fails. Interrupted reads
+/// are retried.
+pub fn for_each_line<R, F, E>(reader: R, on_line: F) -> Result<(), E>
+where
+    R: BufRead,
+    F: FnMut(&str) -> Result<(), E>,
+    E: From<io::Error>,
+{
+    for_each_frame(reader, &LineFramer::default(), on_line)
+}
+
+/// Like [for_each_line], but with frames cut out by `framer` instead of
+/// lines. Frames that aren't utf-8 fail with [io::ErrorKind::InvalidData].
+pub fn for_each_frame<R, Fr, F, E>(mut reader: R, framer: &Fr, mut on_frame: F) -> Result<(), E>
+where
+    R: BufRead,
+    Fr: Framer,
+    F: FnMut(&str) -> Result<(), E>,
+    E: From<io::Error>,
+{
+    while let Some(frame) = framer.read_frame(&mut reader)? {
+        let frame = std::str::from_utf8(&frame)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        on_frame(frame)?;
+    }
+    Ok(())
+}
+
+/// A path in the temp dir for a test to put a file at, that
+/// no other test (nor another run of the same tests) uses.
+#[cfg(test)]
+pub(crate) fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("maelstrom-{}-{name}", std::process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::VecDeque, io::Read};
+
+    /// Hands out its reads one by one, errors included.
+    struct Scripted(VecDeque<io::Result<&'static [u8]>>);
+
+    impl Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.pop_front() {
+                None => Ok(0),
+                Some(Err(err)) => Err(err),
+                Some(Ok(bytes)) => {
+                    buf[..bytes.len()].copy_from_slice(bytes);
+                    Ok(bytes.len())
+                }
+            }
+        }
+    }
+
+    fn lines_from(reads: Vec<io::Result<&'static [u8]>>) -> (Vec<String>, io::Result<()>) {
+        let mut lines = Vec::new();
+        let reader = io::BufReader::new(Scripted(reads.into()));
+        let result = for_each_line(reader, |line| {
+            lines.push(line.to_owned());
+            Ok::<_, io::Error>(())
+        });
+        (lines, result)
+    }
+
+    #[test]
+    fn interrupted_reads_are_retried() {
+        let (lines, result) = lines_from(vec![
+            Ok(b"one\n"),
+            Err(io::ErrorKind::Interrupted.into()),
+            Ok(b"two\r\n"),
+        ]);
+        assert!(result.is_ok());
+        assert_eq!(lines, ["one", "two"]);
+    }
+
+    #[test]
+    fn other_read_errors_stop_us() {
+        let (lines, result) = lines_from(vec![
+            Ok(b"one\n"),
+            Err(io::ErrorKind::BrokenPipe.into()),
+            Ok(b"two\n"),
+        ]);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(lines, ["one"]);
     }
 }
@@ -0,0 +1,140 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+/// How many of the most significant bits of a value pick its bucket.
+/// Three bits keep every bucket within 25% of the values it holds.
+const SUB_BUCKET_BITS: u32 = 3;
+const SUB_BUCKETS: usize = 1 << (SUB_BUCKET_BITS - 1);
+const BUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 2) * SUB_BUCKETS;
+
+/// An HDR-style histogram of durations, in microseconds.
+///
+/// Buckets grow exponentially, so it stays small no
+/// matter how far apart the samples are.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; BUCKETS],
+            count: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn bucket_of(micros: u64) -> usize {
+        let bits = 64 - micros.leading_zeros();
+        if bits <= SUB_BUCKET_BITS {
+            return micros as usize;
+        }
+        let shift = bits - SUB_BUCKET_BITS;
+        ((shift as usize) << (SUB_BUCKET_BITS - 1)) + (micros >> shift) as usize
+    }
+
+    fn lower_bound_of(bucket: usize) -> u64 {
+        if bucket < 2 * SUB_BUCKETS {
+            return bucket as u64;
+        }
+        let shift = (bucket / SUB_BUCKETS) - 1;
+        let mantissa = (bucket % SUB_BUCKETS + SUB_BUCKETS) as u64;
+        mantissa << shift
+    }
+
+    pub fn record(&mut self, elapsed: Duration) {
+        let micros = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        self.buckets[Self::bucket_of(micros)] += 1;
+        self.count += 1;
+    }
+
+    /// How many samples we've recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The (approximate) duration that `quantile` of the samples fall
+    /// under, e.g. `0.99` for the p99.
+    pub fn quantile(&self, quantile: f64) -> Duration {
+        let target = ((quantile.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Duration::from_micros(Self::lower_bound_of(bucket));
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+/// Latency histograms, by name, that get dumped
+/// to stderr when the node goes away.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    histograms: RefCell<BTreeMap<&'static str, Histogram>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Time something until the returned guard is dropped.
+    pub fn timer(&self, name: &'static str) -> Timer<'_> {
+        Timer {
+            metrics: self,
+            name,
+            started_at: Instant::now(),
+        }
+    }
+
+    pub fn record(&self, name: &'static str, elapsed: Duration) {
+        self.histograms
+            .borrow_mut()
+            .entry(name)
+            .or_default()
+            .record(elapsed);
+    }
+
+    /// Write the p50 and p99 of every histogram to stderr.
+    pub fn report(&self) {
+        for (name, histogram) in self.histograms.borrow().iter() {
+            eprintln!(
+                "{name}: count={} p50={:?} p99={:?}",
+                histogram.count(),
+                histogram.quantile(0.5),
+                histogram.quantile(0.99)
+            );
+        }
+    }
+}
+
+impl Drop for Metrics {
+    fn drop(&mut self) {
+        self.report();
+    }
+}
+
+/// Records how long it's been alive into its histogram when dropped.
+pub struct Timer<'a> {
+    metrics: &'a Metrics,
+    name: &'static str,
+    started_at: Instant,
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        self.metrics.record(self.name, self.started_at.elapsed());
+    }
+}
@@ -0,0 +1,254 @@
+use crate::{rng::SplitMix64, Envelope, Respond};
+use serde::Serialize;
+use std::{collections::HashSet, hash::Hash};
+
+/// What should happen to an inbound envelope
+/// once a middleware has had a look at it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// Hand it over to the next middleware (or the node).
+    Continue,
+    /// Swallow it. Nobody after us gets to see it.
+    Drop,
+}
+
+/// Anything that wants to see inbound envelopes before the node does,
+/// e.g. to drop duplicates, record metrics, or enforce a max size.
+pub type Middleware<M> = Box<dyn FnMut(&Envelope<M>) -> Flow + Send>;
+
+/// An ordered list of middlewares that every inbound
+/// envelope runs through before it reaches the node.
+pub struct InboundChain<M> {
+    middlewares: Vec<Middleware<M>>,
+}
+
+impl<M> Default for InboundChain<M> {
+    fn default() -> Self {
+        Self {
+            middlewares: Vec::new(),
+        }
+    }
+}
+
+impl<M> InboundChain<M> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Append a middleware to the end of the chain.
+    pub fn with_middleware(mut self, middleware: Middleware<M>) -> Self {
+        self.middlewares.push(middleware);
+        self
+    }
+
+    /// Run the envelope through every middleware in order,
+    /// stopping at the first one that wants it dropped.
+    pub fn process(&mut self, envelope: &Envelope<M>) -> Flow {
+        for middleware in self.middlewares.iter_mut() {
+            if middleware(envelope) == Flow::Drop {
+                return Flow::Drop;
+            }
+        }
+        Flow::Continue
+    }
+}
+
+/// A middleware that drops (roughly) `rate` of the envelopes other nodes
+/// send us, to check that retries and anti-entropy make up for lost messages.
+/// The choice is seeded, so a run can be reproduced. Clients always get through.
+pub fn lossy<M: Serialize + 'static>(rate: f64, seed: u64) -> Middleware<M> {
+    let mut rng = SplitMix64::new(seed);
+    Box::new(move |envelope| {
+        if envelope.is_internal() && rng.next_f64() < rate {
+            Flow::Drop
+        } else {
+            Flow::Continue
+        }
+    })
+}
+
+/// A middleware that drops envelopes we've already let through once.
+///
+/// What makes two envelopes the same is up to `key`, e.g. the `message`
+/// of a broadcast, or `(src, delivery_id)` of a sync. Envelopes it returns
+/// `None` for are never deduplicated.
+///
+/// A duplicate still gets its plain ok (see [Respond]) if it has one, so
+/// whoever sent it again, e.g. a client retrying a broadcast whose
+/// `broadcast_ok` got lost, isn't left waiting on an answer forever.
+pub fn dedup<M, K, F>(mut key: F) -> Middleware<M>
+where
+    M: Respond + Serialize + 'static,
+    F: FnMut(&Envelope<M>) -> Option<K> + Send + 'static,
+    K: Hash + Eq + Send + 'static,
+{
+    let mut seen = HashSet::new();
+    Box::new(move |envelope| match key(envelope) {
+        Some(key) if seen.contains(&key) => {
+            if let Some(ok) = envelope.reply_ok() {
+                ok.send();
+            }
+            Flow::Drop
+        }
+        Some(key) => {
+            seen.insert(key);
+            Flow::Continue
+        }
+        None => Flow::Continue,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capture::capturing, drain_outbound};
+    use serde_json::Value;
+
+    #[derive(Debug, Clone, Serialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Message {
+        Broadcast { message: usize },
+        BroadcastOk,
+        Sync { delivery_id: usize },
+        SyncOk,
+        Read,
+    }
+
+    impl Respond for Message {
+        fn ok_for(&self, _in_reply_to: Option<usize>) -> Option<Self> {
+            match self {
+                Message::Broadcast { .. } => Some(Message::BroadcastOk),
+                Message::Sync { .. } => Some(Message::SyncOk),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Debug, Hash, PartialEq, Eq)]
+    enum Key {
+        Broadcast(usize),
+        Sync(String, usize),
+    }
+
+    fn chain() -> InboundChain<Message> {
+        InboundChain::new().with_middleware(dedup(|envelope: &Envelope<Message>| {
+            match envelope.message() {
+                Message::Broadcast { message } => Some(Key::Broadcast(*message)),
+                Message::Sync { delivery_id } => {
+                    Some(Key::Sync(envelope.src.to_string(), *delivery_id))
+                }
+                _ => None,
+            }
+        }))
+    }
+
+    /// Run a message from `src` through `chain`, and see what got sent back.
+    fn process(
+        chain: &mut InboundChain<Message>,
+        src: &str,
+        message: Message,
+    ) -> (Flow, Vec<Envelope<Value>>) {
+        let flow = chain.process(&Envelope::new(src, "n1", None, message));
+        (flow, drain_outbound())
+    }
+
+    #[test]
+    fn each_message_type_dedups_by_its_own_key() {
+        let _capture = capturing();
+        let mut chain = chain();
+        let broadcast = || Message::Broadcast { message: 7 };
+        let sync = || Message::Sync { delivery_id: 7 };
+
+        assert_eq!(process(&mut chain, "c1", broadcast()).0, Flow::Continue);
+        // Same message from a different client is still the same broadcast.
+        assert_eq!(process(&mut chain, "c2", broadcast()).0, Flow::Drop);
+
+        assert_eq!(process(&mut chain, "n2", sync()).0, Flow::Continue);
+        assert_eq!(process(&mut chain, "n2", sync()).0, Flow::Drop);
+        // But a delivery_id is only unique per sender.
+        assert_eq!(process(&mut chain, "n3", sync()).0, Flow::Continue);
+
+        // Nothing without a key is ever dropped.
+        for _ in 0..2 {
+            assert_eq!(process(&mut chain, "c1", Message::Read).0, Flow::Continue);
+        }
+    }
+
+    #[test]
+    fn middlewares_run_in_order_until_one_drops() {
+        use std::sync::{Arc, Mutex};
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let record = |name: &'static str, drop_reads: bool| -> Middleware<Message> {
+            let seen = seen.clone();
+            Box::new(move |envelope| {
+                seen.lock().unwrap().push(name);
+                match envelope.message() {
+                    Message::Read if drop_reads => Flow::Drop,
+                    _ => Flow::Continue,
+                }
+            })
+        };
+        let mut chain = InboundChain::new()
+            .with_middleware(record("first", true))
+            .with_middleware(record("second", false));
+
+        let broadcast = Envelope::new("c1", "n1", None, Message::Broadcast { message: 1 });
+        assert_eq!(chain.process(&broadcast), Flow::Continue);
+        assert_eq!(*seen.lock().unwrap(), ["first", "second"]);
+
+        seen.lock().unwrap().clear();
+        let read = Envelope::new("c1", "n1", None, Message::Read);
+        assert_eq!(chain.process(&read), Flow::Drop);
+        assert_eq!(*seen.lock().unwrap(), ["first"], "second never sees it");
+    }
+
+    #[test]
+    fn lossy_drops_about_its_rate_the_same_way_every_time() {
+        let sync = Envelope::new("n2", "n1", None, Message::Sync { delivery_id: 1 });
+        let drops = |seed| {
+            let mut lose = lossy::<Message>(0.3, seed);
+            (0..10_000)
+                .map(|_| lose(&sync) == Flow::Drop)
+                .collect::<Vec<_>>()
+        };
+
+        let dropped = drops(42);
+        assert_eq!(dropped, drops(42));
+        assert_ne!(dropped, drops(43));
+        let count = dropped.iter().filter(|&&dropped| dropped).count();
+        assert!((2_700..3_300).contains(&count), "dropped {count}");
+
+        let mut lose = lossy::<Message>(1.0, 42);
+        let broadcast = Envelope::new("c1", "n1", None, Message::Broadcast { message: 1 });
+        assert_eq!(
+            lose(&broadcast),
+            Flow::Continue,
+            "clients always get through"
+        );
+        assert_eq!(lose(&sync), Flow::Drop);
+    }
+
+    #[test]
+    fn duplicates_still_get_acked() {
+        let _capture = capturing();
+        let mut chain = chain();
+        let (_, sent) = process(&mut chain, "c1", Message::Broadcast { message: 1 });
+        assert!(sent.is_empty(), "the first one is up to the node to answer");
+
+        let retry = Envelope::new("c1", "n1", None, Message::Broadcast { message: 1 });
+        assert_eq!(chain.process(&retry), Flow::Drop);
+        let sent = drain_outbound();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].message()["type"], "broadcast_ok");
+        assert_eq!(sent[0].dest, "c1");
+        assert_eq!(sent[0].in_reply_to(), retry.msg_id());
+
+        let (flow, sent) = process(&mut chain, "n2", Message::Sync { delivery_id: 1 });
+        assert_eq!(flow, Flow::Continue);
+        assert!(sent.is_empty());
+        let (flow, sent) = process(&mut chain, "n2", Message::Sync { delivery_id: 1 });
+        assert_eq!(flow, Flow::Drop);
+        assert_eq!(sent[0].message()["type"], "sync_ok");
+    }
+}
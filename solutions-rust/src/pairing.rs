@@ -0,0 +1,113 @@
+use crate::{Envelope, ErrorCode, ErrorMessage};
+use serde::Serialize;
+
+/// What role a message plays in the request/response dance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    /// A request, answered by the named response variant.
+    Request { response: &'static str },
+    /// A response to the named request variant.
+    Response { request: &'static str },
+    /// Neither, e.g. an `error` or a fire-and-forget gossip.
+    OneWay,
+}
+
+/// Statically pairs every request variant of a message
+/// enum with the variant that answers it.
+///
+/// Implement it with [request_response_pairs], which matches exhaustively
+/// over the enum, so adding a variant without saying how it pairs up
+/// (or pairing it with a response that doesn't exist) fails to compile.
+pub trait RequestResponsePair {
+    fn kind(&self) -> MessageKind;
+}
+
+/// Implement [RequestResponsePair] for a message enum by listing each
+/// request with its response, and (optionally) the one-way variants.
+///
+/// ```text
+/// request_response_pairs!(Message {
+///     Init => InitOk,
+///     Echo => EchoOk,
+/// });
+/// ```
+#[macro_export]
+macro_rules! request_response_pairs {
+    ($message:ident { $($request:ident => $response:ident),* $(,)? } $(, one_way { $($other:ident),* $(,)? })? $(,)?) => {
+        impl $crate::RequestResponsePair for $message {
+            fn kind(&self) -> $crate::MessageKind {
+                match self {
+                    $(
+                        $message::$request { .. } => $crate::MessageKind::Request {
+                            response: stringify!($response),
+                        },
+                        $message::$response { .. } => $crate::MessageKind::Response {
+                            request: stringify!($request),
+                        },
+                    )*
+                    $($(
+                        $message::$other { .. } => $crate::MessageKind::OneWay,
+                    )*)?
+                }
+            }
+        }
+    };
+}
+
+/// Knows the plain success response to each request that
+/// doesn't need anything more than to be acknowledged.
+///
+/// ```
+/// use maelstrom::{Envelope, Respond};
+/// use serde::Serialize;
+///
+/// #[derive(Debug, PartialEq, Serialize)]
+/// #[serde(tag = "type", rename_all = "snake_case")]
+/// enum Message {
+///     Broadcast { message: usize },
+///     BroadcastOk,
+///     Read,
+/// }
+///
+/// impl Respond for Message {
+///     fn ok_for(&self, _in_reply_to: Option<usize>) -> Option<Self> {
+///         match self {
+///             Message::Broadcast { .. } => Some(Message::BroadcastOk),
+///             _ => None,
+///         }
+///     }
+/// }
+///
+/// let request = Envelope::new("c1", "n1", None, Message::Broadcast { message: 7 });
+/// let reply = request.reply_ok().unwrap();
+/// assert_eq!(reply.message(), &Message::BroadcastOk);
+/// assert_eq!(reply.in_reply_to(), request.msg_id());
+///
+/// let read = Envelope::new("c1", "n1", None, Message::Read);
+/// assert!(read.reply_ok().is_none());
+/// ```
+pub trait Respond: Sized {
+    /// The success response to this request (the one with `msg_id`
+    /// `in_reply_to`), if there's one that doesn't carry anything more
+    /// than that (e.g. `broadcast_ok`, but not `read_ok`).
+    fn ok_for(&self, in_reply_to: Option<usize>) -> Option<Self>;
+
+    /// The error response to this request.
+    fn err(&self, code: ErrorCode, text: impl Into<String>) -> ErrorMessage {
+        ErrorMessage::new(code, text)
+    }
+}
+
+impl<M: Respond + Serialize> Envelope<M> {
+    /// Answer this envelope with its plain success response, if it has one.
+    pub fn reply_ok(&self) -> Option<Envelope<M>> {
+        self.message()
+            .ok_for(self.msg_id())
+            .map(|ok| self.reply(ok))
+    }
+
+    /// Answer this envelope with an error.
+    pub fn reply_err(&self, code: ErrorCode, text: impl Into<String>) -> Envelope<ErrorMessage> {
+        self.defer().reply(self.message().err(code, text))
+    }
+}
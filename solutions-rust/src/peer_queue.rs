@@ -0,0 +1,106 @@
+use crate::{Envelope, NodeId};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+
+/// Outbound messages, queued up per peer.
+///
+/// Both the immediate forward and the periodic sync can queue things up
+/// for the same neighbor; as long as everything goes through here and
+/// gets sent by [PeerQueue::flush], each neighbor gets its messages
+/// in the order they were queued.
+#[derive(Debug)]
+pub struct PeerQueue<M> {
+    queues: BTreeMap<NodeId, VecDeque<M>>,
+}
+
+impl<M> Default for PeerQueue<M> {
+    fn default() -> Self {
+        Self {
+            queues: BTreeMap::new(),
+        }
+    }
+}
+
+impl<M> PeerQueue<M> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Queue `message` up behind whatever's already waiting for `peer`.
+    pub fn push(&mut self, peer: &NodeId, message: M) {
+        self.queues
+            .entry(peer.clone())
+            .or_default()
+            .push_back(message);
+    }
+
+    /// Take the oldest message waiting for `peer`.
+    pub fn pop(&mut self, peer: &NodeId) -> Option<M> {
+        self.queues.get_mut(peer)?.pop_front()
+    }
+
+    /// How many messages are waiting for `peer`.
+    pub fn len(&self, peer: &NodeId) -> usize {
+        self.queues.get(peer).map(VecDeque::len).unwrap_or_default()
+    }
+
+    /// Whether nothing's waiting for anyone.
+    pub fn is_empty(&self) -> bool {
+        self.queues.values().all(VecDeque::is_empty)
+    }
+}
+
+impl<M: Serialize> PeerQueue<M> {
+    /// Send everything that's queued up, oldest first for every peer.
+    pub fn flush(&mut self, src: &NodeId) {
+        for (peer, queue) in self.queues.iter_mut() {
+            for message in queue.drain(..) {
+                Envelope::new(src, peer, None, message).send();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capture::capturing, drain_outbound};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn each_peer_gets_its_messages_in_order() {
+        let _capture = capturing();
+        let (n2, n3) = (NodeId::from("n2"), NodeId::from("n3"));
+        let mut queue = PeerQueue::new();
+        // e.g. a forward, then a sync, to the same neighbor.
+        queue.push(&n2, json!({"type": "broadcast", "message": 1}));
+        queue.push(&n3, json!({"type": "broadcast", "message": 1}));
+        queue.push(&n2, json!({"type": "sync", "messages": [1, 2]}));
+        assert_eq!((queue.len(&n2), queue.len(&n3)), (2, 1));
+
+        queue.flush(&NodeId::from("n1"));
+        assert!(queue.is_empty());
+        let sent = drain_outbound();
+        let to = |peer: &str| -> Vec<Value> {
+            sent.iter()
+                .filter(|envelope| envelope.dest == peer)
+                .map(|envelope| envelope.message()["type"].clone())
+                .collect()
+        };
+        assert_eq!(to("n2"), ["broadcast", "sync"]);
+        assert_eq!(to("n3"), ["broadcast"]);
+        assert!(sent.iter().all(|envelope| envelope.src == "n1"));
+    }
+
+    #[test]
+    fn pop_takes_the_oldest() {
+        let peer = NodeId::from("n2");
+        let mut queue = PeerQueue::new();
+        queue.push(&peer, 1);
+        queue.push(&peer, 2);
+        assert_eq!(queue.pop(&peer), Some(1));
+        assert_eq!(queue.pop(&peer), Some(2));
+        assert_eq!(queue.pop(&peer), None);
+        assert_eq!(queue.pop(&NodeId::from("n3")), None);
+    }
+}
@@ -0,0 +1,202 @@
+use crate::{Clock, Envelope, NodeId, SystemClock};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+/// How far along a [Quorum] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuorumStatus {
+    /// Still waiting on enough replies.
+    Pending,
+    /// Enough peers answered.
+    Reached,
+    /// Too few peers answered in time.
+    TimedOut,
+}
+
+/// The smallest number of nodes out of `cluster_size` that makes a majority.
+pub fn majority(cluster_size: usize) -> usize {
+    cluster_size / 2 + 1
+}
+
+/// A request sent to a bunch of peers at once, that's done as soon as
+/// `needed` of them answer, e.g. a read that wants a majority's view
+/// rather than whatever a single neighbor happens to know.
+#[derive(Debug)]
+pub struct Quorum<M, C = SystemClock> {
+    /// The msg_ids of the requests nobody has answered yet.
+    waiting: HashSet<usize>,
+    needed: usize,
+    replies: Vec<Envelope<M>>,
+    deadline: Instant,
+    clock: C,
+}
+
+impl<M: Serialize + Clone> Quorum<M> {
+    /// Send `request` from us to every one of `peers`.
+    pub fn send(
+        src: &NodeId,
+        peers: &[NodeId],
+        request: M,
+        needed: usize,
+        timeout: Duration,
+    ) -> Self {
+        Self::send_with_clock(src, peers, request, needed, timeout, SystemClock)
+    }
+}
+
+impl<M: Serialize + Clone, C: Clock> Quorum<M, C> {
+    /// Like [Quorum::send], but timing out by `clock`.
+    pub fn send_with_clock(
+        src: &NodeId,
+        peers: &[NodeId],
+        request: M,
+        needed: usize,
+        timeout: Duration,
+        clock: C,
+    ) -> Self {
+        let waiting = peers
+            .iter()
+            .filter_map(|peer| {
+                let envelope = Envelope::new(src, peer, None, request.clone());
+                envelope.send();
+                envelope.msg_id()
+            })
+            .collect();
+
+        Self {
+            waiting,
+            needed,
+            replies: Vec::new(),
+            deadline: clock.now() + timeout,
+            clock,
+        }
+    }
+
+    /// Take in `reply`, if it answers one of our requests.
+    /// Returns whether it did.
+    pub fn receive(&mut self, reply: &Envelope<M>) -> bool {
+        let ours = reply
+            .in_reply_to()
+            .is_some_and(|in_reply_to| self.waiting.remove(&in_reply_to));
+        if ours {
+            self.replies.push(reply.clone());
+        }
+        ours
+    }
+
+    pub fn status(&self) -> QuorumStatus {
+        if self.replies.len() >= self.needed {
+            QuorumStatus::Reached
+        } else if self.clock.now() >= self.deadline
+            || self.replies.len() + self.waiting.len() < self.needed
+        {
+            QuorumStatus::TimedOut
+        } else {
+            QuorumStatus::Pending
+        }
+    }
+
+    /// Whatever came back so far.
+    pub fn replies(&self) -> &[Envelope<M>] {
+        &self.replies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capture::capturing, drain_outbound, ManualClock};
+    use serde::Deserialize;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Message {
+        Read,
+        ReadOk { value: usize },
+    }
+
+    fn peers() -> Vec<NodeId> {
+        ["n2", "n3", "n4"].map(NodeId::from).to_vec()
+    }
+
+    /// Ask every peer, and hand back what each one was sent.
+    fn ask(
+        needed: usize,
+        clock: &ManualClock,
+    ) -> (Quorum<Message, ManualClock>, Vec<Envelope<Message>>) {
+        let quorum = Quorum::send_with_clock(
+            &NodeId::from("n1"),
+            &peers(),
+            Message::Read,
+            needed,
+            TIMEOUT,
+            clock.clone(),
+        );
+        let sent = drain_outbound()
+            .into_iter()
+            .map(|envelope| {
+                serde_json::from_value(serde_json::to_value(envelope).unwrap()).unwrap()
+            })
+            .collect();
+        (quorum, sent)
+    }
+
+    fn answer(request: &Envelope<Message>, value: usize) -> Envelope<Message> {
+        request.reply(Message::ReadOk { value })
+    }
+
+    #[test]
+    fn majority_is_enough_without_the_slow_peer() {
+        let _capture = capturing();
+        let clock = ManualClock::new();
+        // A majority of the four of us, counting ourselves.
+        let (mut quorum, sent) = ask(majority(4) - 1, &clock);
+        assert_eq!(sent.len(), 3);
+
+        assert!(quorum.receive(&answer(&sent[0], 1)));
+        assert_eq!(quorum.status(), QuorumStatus::Pending);
+        clock.advance(TIMEOUT / 2);
+        assert!(quorum.receive(&answer(&sent[2], 1)));
+        // n3 never answers, and doesn't have to.
+        assert_eq!(quorum.status(), QuorumStatus::Reached);
+        clock.advance(TIMEOUT);
+        assert_eq!(quorum.status(), QuorumStatus::Reached);
+        assert_eq!(quorum.replies().len(), 2);
+
+        // Nor does anything that isn't a reply to one of ours count.
+        assert!(!quorum.receive(&answer(&sent[0], 2)));
+        assert!(!quorum.receive(&Envelope::new(
+            "n3",
+            "n1",
+            None,
+            Message::ReadOk { value: 1 }
+        )));
+    }
+
+    #[test]
+    fn a_slow_peer_times_everyone_out() {
+        let _capture = capturing();
+        let clock = ManualClock::new();
+        let (mut quorum, sent) = ask(3, &clock);
+        quorum.receive(&answer(&sent[0], 1));
+        quorum.receive(&answer(&sent[1], 1));
+
+        clock.advance(TIMEOUT - Duration::from_millis(1));
+        assert_eq!(quorum.status(), QuorumStatus::Pending);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(quorum.status(), QuorumStatus::TimedOut);
+        assert_eq!(quorum.replies().len(), 2);
+    }
+
+    #[test]
+    fn too_few_peers_left_is_a_timeout_right_away() {
+        let _capture = capturing();
+        let (quorum, _) = ask(4, &ManualClock::new());
+        assert_eq!(quorum.status(), QuorumStatus::TimedOut);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{envelope::send_line, Address, Body, Envelope};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::value::RawValue;
+
+/// An envelope whose body is left as the raw json it came in as, so a
+/// gateway can look at `src`, `dest` and the `type` tag, and only decode
+/// the rest (with [RawEnvelope::deserialize_body_as]) if it has to. Whatever
+/// it just passes on (with [RawEnvelope::forward]) goes back out byte for byte.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RawEnvelope {
+    pub src: Address,
+    pub dest: Address,
+    body: Box<RawValue>,
+}
+
+/// Just the `type` tag of a body, skipping over everything else.
+#[derive(Deserialize)]
+struct Tag {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+}
+
+impl RawEnvelope {
+    /// The body, exactly as it came in.
+    pub fn raw_body(&self) -> &str {
+        self.body.get()
+    }
+
+    /// The body's `type` tag, if it has one.
+    pub fn message_type(&self) -> Option<String> {
+        serde_json::from_str::<Tag>(self.body.get())
+            .ok()
+            .and_then(|tag| tag.kind)
+    }
+
+    /// Decode the body after all, as a regular [Envelope].
+    pub fn deserialize_body_as<T: DeserializeOwned>(&self) -> serde_json::Result<Envelope<T>> {
+        let body: Body<T> = serde_json::from_str(self.body.get())?;
+        Ok(Envelope {
+            src: self.src.clone(),
+            dest: self.dest.clone(),
+            body,
+        })
+    }
+
+    /// Pass this on to `dest`, from us, with the body untouched,
+    /// msg_id and in_reply_to included.
+    pub fn forward(&self, dest: &str) -> RawEnvelope {
+        RawEnvelope {
+            src: self.dest.clone(),
+            dest: dest.into(),
+            body: self.body.clone(),
+        }
+    }
+
+    /// Write the envelope out, same as [Envelope::send].
+    pub fn send(&self) {
+        send_line(serde_json::to_vec(self).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{capturing, drain_outbound_bytes};
+
+    #[derive(Debug, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Message {
+        Echo { echo: String },
+    }
+
+    fn raw(line: &str) -> RawEnvelope {
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn forwarding_leaves_the_body_alone() {
+        let _capture = capturing();
+        // Not ours to decode, with spacing, key order and a number
+        // that wouldn't survive a trip through a `Value` as they are.
+        let body = r#"{"type":"mystery",  "msg_id":3,"z":1.50e3,"a":[]}"#;
+        let envelope = raw(&format!(r#"{{"src":"c1","dest":"n1","body":{body}}}"#));
+        assert!(envelope.deserialize_body_as::<Message>().is_err());
+
+        envelope.forward("n2").send();
+        let sent = String::from_utf8(drain_outbound_bytes()).unwrap();
+        assert_eq!(
+            sent,
+            format!(
+                r#"{{"src":"n1","dest":"n2","body":{body}}}
+"#
+            )
+        );
+    }
+
+    #[test]
+    fn the_type_tag_is_read_without_the_rest() {
+        let tagged = raw(r#"{"src":"c1","dest":"n1","body":{"type":"echo","echo":{"x":1}}}"#);
+        assert_eq!(tagged.message_type().as_deref(), Some("echo"));
+        // Our message type wouldn't take it, but that's none of message_type's business.
+        assert!(tagged.deserialize_body_as::<Message>().is_err());
+
+        let untagged = raw(r#"{"src":"c1","dest":"n1","body":{"echo":"hi"}}"#);
+        assert_eq!(untagged.message_type(), None);
+        let not_an_object = raw(r#"{"src":"c1","dest":"n1","body":[1,2]}"#);
+        assert_eq!(not_an_object.message_type(), None);
+    }
+
+    #[test]
+    fn the_body_decodes_on_demand() {
+        let envelope =
+            raw(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":4,"echo":"hi"}}"#);
+        let decoded = envelope.deserialize_body_as::<Message>().unwrap();
+        assert_eq!((decoded.src.as_str(), decoded.dest.as_str()), ("c1", "n1"));
+        assert_eq!(decoded.msg_id(), Some(4));
+        let Message::Echo { echo } = decoded.message();
+        assert_eq!(echo, "hi");
+    }
+}
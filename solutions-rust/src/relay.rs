@@ -0,0 +1,252 @@
+use crate::{Address, Envelope, NodeId, ReplyToken};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Proxies client requests to whichever node owns them (see
+/// [crate::ConsistentHash]), and passes the owner's reply back to the
+/// client, as if we'd answered it ourselves.
+///
+/// The owner handles a forwarded request like any other, and replies to us.
+#[derive(Debug, Default)]
+pub struct Relay {
+    /// Who asked, by the msg_id of the request we forwarded.
+    waiting: HashMap<usize, ReplyToken>,
+}
+
+impl Relay {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Send `request` on to `owner`, and remember who to answer once it replies.
+    pub fn forward<M: Serialize + Clone>(&mut self, request: &Envelope<M>, owner: &NodeId) {
+        let forwarded = request.forward_body(owner);
+        if let Some(msg_id) = forwarded.msg_id() {
+            self.waiting.insert(msg_id, request.defer());
+        }
+        forwarded.send();
+    }
+
+    /// If `reply` answers something we forwarded, send it back to whoever
+    /// asked in the first place. Returns whether it did.
+    pub fn relay<M: Serialize + Clone>(&mut self, reply: &Envelope<M>) -> bool {
+        let Some(original) = reply
+            .in_reply_to()
+            .and_then(|in_reply_to| self.waiting.remove(&in_reply_to))
+        else {
+            return false;
+        };
+        reply.reply_to(&original, reply.message().clone()).send();
+        true
+    }
+
+    /// How many forwarded requests haven't been answered yet.
+    pub fn len(&self) -> usize {
+        self.waiting.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiting.is_empty()
+    }
+}
+
+/// Somebody we owe a reply, on the way back along a [ReturnPath].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hop {
+    pub src: Address,
+    pub msg_id: Option<usize>,
+}
+
+/// Everyone a request went through before it got to us, client first,
+/// so the answer can find its way back one hop at a time, however many
+/// nodes it took to get answered. [Relay], but without anybody having
+/// to remember anything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReturnPath {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    return_path: Vec<Hop>,
+}
+
+impl ReturnPath {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn hops(&self) -> &[Hop] {
+        &self.return_path
+    }
+
+    pub fn len(&self) -> usize {
+        self.return_path.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.return_path.is_empty()
+    }
+}
+
+/// A message that carries its [ReturnPath] along in the body,
+/// right next to the message's own fields.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Routed<M> {
+    #[serde(flatten)]
+    pub path: ReturnPath,
+    #[serde(flatten)]
+    pub message: M,
+}
+
+impl<M> Routed<M> {
+    /// A message that hasn't been anywhere yet.
+    pub fn new(message: M) -> Self {
+        Self {
+            path: ReturnPath::new(),
+            message,
+        }
+    }
+}
+
+impl<M: Serialize + Clone> Envelope<Routed<M>> {
+    /// Pass this request on to `dest`, with whoever sent it to us
+    /// added to the return path.
+    pub fn forward_along(&self, dest: &str) -> Envelope<Routed<M>> {
+        let mut path = self.message().path.clone();
+        path.return_path.push(Hop {
+            src: self.src.clone(),
+            msg_id: self.msg_id(),
+        });
+        Envelope::new(
+            &self.dest,
+            dest,
+            None,
+            Routed {
+                path,
+                message: self.message().message.clone(),
+            },
+        )
+    }
+
+    /// Answer this request, which may have come a long way. The reply goes
+    /// to whoever sent it to us, who passes it on with [Envelope::unwind].
+    pub fn reply_via_return_path(&self, message: M) -> Envelope<Routed<M>> {
+        self.reply(Routed {
+            path: self.message().path.clone(),
+            message,
+        })
+    }
+
+    /// Hand a reply that came back along a return path on to the
+    /// previous hop, which ends up being the client that asked.
+    /// `None` if there's nobody left to hand it to.
+    pub fn unwind(&self) -> Option<Envelope<Routed<M>>> {
+        let mut path = self.message().path.clone();
+        let hop = path.return_path.pop()?;
+        Some(Envelope::new(
+            &self.dest,
+            &hop.src,
+            hop.msg_id,
+            Routed {
+                path,
+                message: self.message().message.clone(),
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capture::capturing, drain_outbound};
+    use serde::de::DeserializeOwned;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Message {
+        Read { key: usize },
+        ReadOk { value: usize },
+    }
+
+    /// The one envelope that's been sent since we last looked.
+    fn sent<M: DeserializeOwned>() -> Envelope<M> {
+        let mut sent = drain_outbound();
+        assert_eq!(sent.len(), 1);
+        serde_json::from_value(serde_json::to_value(sent.remove(0)).unwrap()).unwrap()
+    }
+
+    /// Send `envelope`, and hand back what went over the wire.
+    fn over_the_wire<M: Serialize + DeserializeOwned>(envelope: Envelope<M>) -> Envelope<M> {
+        envelope.send();
+        sent()
+    }
+
+    #[test]
+    fn relayed_replies_go_back_to_whoever_asked() {
+        let _capture = capturing();
+        let mut relay = Relay::new();
+        // n1 doesn't own key 1, n2 does.
+        let request = Envelope::new("c1", "n1", None, Message::Read { key: 1 });
+        relay.forward(&request, &NodeId::from("n2"));
+        let forwarded: Envelope<Message> = sent();
+        assert_eq!(
+            (forwarded.src.as_str(), forwarded.dest.as_str()),
+            ("n1", "n2")
+        );
+        assert_eq!(forwarded.message(), request.message());
+        assert_eq!(relay.len(), 1);
+
+        // Nothing we forwarded, so not ours to relay.
+        let stray = Envelope::new("n3", "n1", Some(12345), Message::ReadOk { value: 0 });
+        assert!(!relay.relay(&stray));
+
+        let answer = forwarded.reply(Message::ReadOk { value: 7 });
+        assert!(relay.relay(&answer));
+        assert!(relay.is_empty());
+        let relayed: Envelope<Message> = sent();
+        assert_eq!((relayed.src.as_str(), relayed.dest.as_str()), ("n1", "c1"));
+        assert_eq!(relayed.in_reply_to(), request.msg_id());
+        assert_eq!(relayed.message(), &Message::ReadOk { value: 7 });
+
+        // And only the once.
+        assert!(!relay.relay(&answer));
+    }
+
+    #[test]
+    fn replies_unwind_back_to_the_client() {
+        let _capture = capturing();
+        let request = Envelope::new("c1", "n1", None, Routed::new(Message::Read { key: 1 }));
+        let at_n2 = over_the_wire(request.forward_along("n2"));
+        let at_n3 = over_the_wire(at_n2.forward_along("n3"));
+        assert_eq!(
+            at_n3.message().path.hops(),
+            [
+                Hop {
+                    src: "c1".into(),
+                    msg_id: request.msg_id()
+                },
+                Hop {
+                    src: "n1".into(),
+                    msg_id: at_n2.msg_id()
+                },
+            ]
+        );
+
+        let answer = over_the_wire(at_n3.reply_via_return_path(Message::ReadOk { value: 7 }));
+        assert_eq!(answer.dest, "n2");
+        assert_eq!(answer.in_reply_to(), at_n3.msg_id());
+
+        let back_at_n1 = over_the_wire(answer.unwind().unwrap());
+        assert_eq!(back_at_n1.dest, "n1");
+        assert_eq!(back_at_n1.in_reply_to(), at_n2.msg_id());
+
+        let back_at_c1 = over_the_wire(back_at_n1.unwind().unwrap());
+        assert_eq!(
+            (back_at_c1.src.as_str(), back_at_c1.dest.as_str()),
+            ("n1", "c1")
+        );
+        assert_eq!(back_at_c1.in_reply_to(), request.msg_id());
+        assert_eq!(back_at_c1.message().message, Message::ReadOk { value: 7 });
+        assert!(back_at_c1.message().path.is_empty());
+
+        // Which is as far back as it goes.
+        assert!(back_at_c1.unwind().is_none());
+    }
+}
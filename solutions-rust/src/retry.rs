@@ -0,0 +1,162 @@
+use crate::{Address, Clock, SystemClock};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// Called with the target we just gave up on.
+pub type GiveUpHandler = Box<dyn FnMut(&Address) + Send>;
+
+/// Caps how often we retry sends to any one target, so that a dead
+/// (or partitioned) peer doesn't get flooded with retries forever.
+///
+/// Once a target has used up `max_retries` within `window`, we give up
+/// on it: the give-up handler is called, and no retries to it are allowed
+/// until `cool_down` has passed, after which it gets a fresh budget.
+pub struct RetryBudget<C = SystemClock> {
+    max_retries: usize,
+    window: Duration,
+    cool_down: Duration,
+    targets: HashMap<Address, TargetBudget>,
+    on_give_up: Option<GiveUpHandler>,
+    clock: C,
+}
+
+#[derive(Debug, Default)]
+struct TargetBudget {
+    /// When we retried lately, oldest first.
+    retries: VecDeque<Instant>,
+    gave_up_at: Option<Instant>,
+}
+
+impl RetryBudget {
+    pub fn new(max_retries: usize, window: Duration, cool_down: Duration) -> Self {
+        Self::with_clock(max_retries, window, cool_down, SystemClock)
+    }
+}
+
+impl<C: Clock> RetryBudget<C> {
+    pub fn with_clock(max_retries: usize, window: Duration, cool_down: Duration, clock: C) -> Self {
+        Self {
+            max_retries,
+            window,
+            cool_down,
+            targets: HashMap::new(),
+            on_give_up: None,
+            clock,
+        }
+    }
+
+    /// Call `handler` whenever we give up on a target.
+    pub fn with_give_up_handler(mut self, handler: GiveUpHandler) -> Self {
+        self.on_give_up = Some(handler);
+        self
+    }
+
+    /// Whether we may retry a send to `target` now. If we may,
+    /// it counts against the target's budget.
+    pub fn try_retry(&mut self, target: &Address) -> bool {
+        let now = self.clock.now();
+        let budget = self.targets.entry(target.clone()).or_default();
+
+        if let Some(gave_up_at) = budget.gave_up_at {
+            if now.saturating_duration_since(gave_up_at) < self.cool_down {
+                return false;
+            }
+            *budget = TargetBudget::default();
+        }
+
+        while budget
+            .retries
+            .front()
+            .is_some_and(|&retried_at| now.saturating_duration_since(retried_at) >= self.window)
+        {
+            budget.retries.pop_front();
+        }
+
+        if budget.retries.len() >= self.max_retries {
+            budget.gave_up_at = Some(now);
+            eprintln!("Giving up on retrying sends to {target} for a while.");
+            if let Some(on_give_up) = self.on_give_up.as_mut() {
+                on_give_up(target);
+            }
+            return false;
+        }
+
+        budget.retries.push_back(now);
+        true
+    }
+
+    /// Whether we've given up on `target`, and it's still cooling down.
+    pub fn is_given_up(&self, target: &Address) -> bool {
+        let now = self.clock.now();
+        self.targets
+            .get(target)
+            .and_then(|budget| budget.gave_up_at)
+            .is_some_and(|gave_up_at| now.saturating_duration_since(gave_up_at) < self.cool_down)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ManualClock;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn a_dead_target_is_given_up_on_until_it_cools_down() {
+        let clock = ManualClock::new();
+        let given_up = Arc::new(Mutex::new(Vec::new()));
+        let seen = given_up.clone();
+        let mut budget = RetryBudget::with_clock(
+            3,
+            Duration::from_secs(10),
+            Duration::from_secs(30),
+            clock.clone(),
+        )
+        .with_give_up_handler(Box::new(move |target| {
+            seen.lock().unwrap().push(target.clone())
+        }));
+        let (dead, alive) = (Address::from("n2"), Address::from("n3"));
+
+        for _ in 0..3 {
+            assert!(budget.try_retry(&dead));
+            clock.advance(Duration::from_secs(1));
+        }
+        assert!(!budget.try_retry(&dead));
+        assert_eq!(*given_up.lock().unwrap(), ["n2"]);
+
+        // Nothing goes through while it cools down, and it's only given up on once.
+        for _ in 0..5 {
+            clock.advance(Duration::from_secs(5));
+            assert!(!budget.try_retry(&dead));
+            assert!(budget.is_given_up(&dead));
+        }
+        assert_eq!(given_up.lock().unwrap().len(), 1);
+        // Other targets have budgets of their own.
+        assert!(budget.try_retry(&alive));
+
+        clock.advance(Duration::from_secs(5));
+        assert!(!budget.is_given_up(&dead));
+        assert!(
+            budget.try_retry(&dead),
+            "a fresh budget after the cool-down"
+        );
+    }
+
+    #[test]
+    fn retries_spread_out_past_the_window_are_fine() {
+        let clock = ManualClock::new();
+        let mut budget = RetryBudget::with_clock(
+            2,
+            Duration::from_secs(10),
+            Duration::from_secs(30),
+            clock.clone(),
+        );
+        let target = Address::from("n2");
+        for _ in 0..10 {
+            assert!(budget.try_retry(&target));
+            clock.advance(Duration::from_secs(6));
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use crate::{Address, Clock, Envelope, SystemClock};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// An rpc we sent out and are waiting to hear back about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingRpc {
+    pub msg_id: usize,
+    pub dest: Address,
+    pub sent_at: Instant,
+}
+
+/// Called with the envelope's source and `in_reply_to`.
+pub type OrphanReplyHandler = Box<dyn FnMut(&Address, usize) + Send>;
+
+/// Correlates replies with the rpcs we sent, by msg_id.
+///
+/// A reply to something we never sent (or already gave up on) is an
+/// orphan: it's logged to stderr and handed to the orphan handler
+/// (if there is one), rather than dropped silently.
+#[derive(Default)]
+pub struct RpcTracker<C = SystemClock> {
+    pending: HashMap<usize, PendingRpc>,
+    on_orphan_reply: Option<OrphanReplyHandler>,
+    clock: C,
+}
+
+impl RpcTracker {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<C: Clock> RpcTracker<C> {
+    /// Tell how long rpcs have been pending by `clock` instead.
+    pub fn with_clock(clock: C) -> Self {
+        Self {
+            pending: HashMap::new(),
+            on_orphan_reply: None,
+            clock,
+        }
+    }
+
+    /// Call `handler` for every orphaned reply.
+    pub fn with_orphan_handler(mut self, handler: OrphanReplyHandler) -> Self {
+        self.on_orphan_reply = Some(handler);
+        self
+    }
+
+    /// Start waiting for a reply to this envelope.
+    pub fn track<M: Serialize>(&mut self, envelope: &Envelope<M>) {
+        if let Some(msg_id) = envelope.msg_id() {
+            self.pending.insert(
+                msg_id,
+                PendingRpc {
+                    msg_id,
+                    dest: envelope.dest.clone(),
+                    sent_at: self.clock.now(),
+                },
+            );
+        }
+    }
+
+    /// Stop waiting for a reply to the given msg_id.
+    pub fn forget(&mut self, msg_id: usize) -> Option<PendingRpc> {
+        self.pending.remove(&msg_id)
+    }
+
+    pub fn is_pending(&self, msg_id: usize) -> bool {
+        self.pending.contains_key(&msg_id)
+    }
+
+    /// Every rpc we're still waiting on, with how long we've been
+    /// waiting, oldest first. Handy to log when a node seems stuck.
+    pub fn pending_rpcs(&self) -> Vec<(usize, Address, Duration)> {
+        let now = self.clock.now();
+        let mut pending: Vec<_> = self.pending.values().collect();
+        pending.sort_by_key(|rpc| (rpc.sent_at, rpc.msg_id));
+        pending
+            .into_iter()
+            .map(|rpc| {
+                (
+                    rpc.msg_id,
+                    rpc.dest.clone(),
+                    now.saturating_duration_since(rpc.sent_at),
+                )
+            })
+            .collect()
+    }
+
+    /// Match a reply up with the rpc it answers. Returns `None` if the
+    /// envelope isn't a reply at all, or if it's an orphan.
+    pub fn resolve<M: Serialize>(&mut self, reply: &Envelope<M>) -> Option<PendingRpc> {
+        let in_reply_to = reply.in_reply_to()?;
+        let resolved = self.pending.remove(&in_reply_to);
+        if resolved.is_none() {
+            eprintln!(
+                "Got a reply from {} to an rpc we aren't waiting on: {}",
+                reply.src, in_reply_to
+            );
+            if let Some(on_orphan_reply) = self.on_orphan_reply.as_mut() {
+                on_orphan_reply(&reply.src, in_reply_to);
+            }
+        }
+        resolved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Message {
+        Read,
+        ReadOk { value: usize },
+    }
+
+    #[test]
+    fn orphan_replies_go_to_the_handler() {
+        let orphans = Arc::new(Mutex::new(Vec::new()));
+        let seen = orphans.clone();
+        let mut rpcs = RpcTracker::new().with_orphan_handler(Box::new(move |src, in_reply_to| {
+            seen.lock().unwrap().push((src.clone(), in_reply_to));
+        }));
+
+        let request = Envelope::new("n1", "n2", None, Message::Read);
+        rpcs.track(&request);
+        let unknown = request.msg_id().unwrap() + 1000;
+        let orphan = Envelope::new("n2", "n1", Some(unknown), Message::ReadOk { value: 1 });
+
+        assert_eq!(rpcs.resolve(&orphan), None);
+        assert_eq!(*orphans.lock().unwrap(), [("n2".into(), unknown)]);
+        assert!(rpcs.is_pending(request.msg_id().unwrap()));
+
+        // The real reply still gets through, but only once.
+        let reply = request.reply(Message::ReadOk { value: 1 });
+        assert_eq!(rpcs.resolve(&reply).unwrap().dest, "n2");
+        assert_eq!(rpcs.resolve(&reply), None);
+        assert_eq!(orphans.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn pending_rpcs_age_with_the_clock() {
+        let clock = crate::ManualClock::new();
+        let mut rpcs = RpcTracker::with_clock(clock.clone());
+        let first = Envelope::new("n1", "n2", None, Message::Read);
+        rpcs.track(&first);
+        clock.advance(Duration::from_millis(30));
+        let second = Envelope::new("n1", "seq-kv", None, Message::Read);
+        rpcs.track(&second);
+        clock.advance(Duration::from_millis(20));
+
+        assert_eq!(
+            rpcs.pending_rpcs(),
+            [
+                (
+                    first.msg_id().unwrap(),
+                    "n2".into(),
+                    Duration::from_millis(50)
+                ),
+                (
+                    second.msg_id().unwrap(),
+                    "seq-kv".into(),
+                    Duration::from_millis(20)
+                ),
+            ]
+        );
+
+        clock.advance(Duration::from_millis(100));
+        rpcs.resolve(&first.reply(Message::ReadOk { value: 1 }));
+        assert_eq!(
+            rpcs.pending_rpcs(),
+            [(
+                second.msg_id().unwrap(),
+                "seq-kv".into(),
+                Duration::from_millis(120)
+            )]
+        );
+    }
+
+    #[test]
+    fn orphans_without_a_handler_dont_panic() {
+        let mut rpcs = RpcTracker::new();
+        let orphan = Envelope::new("n2", "n1", Some(12345), Message::ReadOk { value: 1 });
+        assert_eq!(rpcs.resolve(&orphan), None);
+        // Not a reply at all.
+        assert_eq!(
+            rpcs.resolve(&Envelope::new("n2", "n1", None, Message::Read)),
+            None
+        );
+    }
+}
@@ -0,0 +1,293 @@
+use crate::{Clock, ConsistentHash, Envelope, NodeId, StableHash, SystemClock};
+use serde::Serialize;
+use std::{
+    collections::{hash_map::RandomState, HashMap, HashSet, VecDeque},
+    hash::{BuildHasher, Hash},
+    time::{Duration, Instant},
+};
+
+/// Whether [SeenSet::insert_or_ack] saw something for the first time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertOutcome {
+    /// First time, so it probably needs passing on.
+    New,
+    /// We've had it already, acking it is all there is to do.
+    AlreadySeen,
+}
+
+/// Everything we've seen so far, e.g. every broadcast message.
+///
+/// Hashes with std's hasher, unless given another one, e.g.
+/// `SeenSet<usize, FxBuildHasher>` for integer ids on a hot path.
+#[derive(Debug, Clone)]
+pub struct SeenSet<T, S = RandomState> {
+    seen: HashSet<T, S>,
+}
+
+impl<T, S: Default> Default for SeenSet<T, S> {
+    fn default() -> Self {
+        Self {
+            seen: HashSet::default(),
+        }
+    }
+}
+
+impl<T: Hash + Eq> SeenSet<T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<T: Hash + Eq, S: BuildHasher> SeenSet<T, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            seen: HashSet::with_hasher(hasher),
+        }
+    }
+
+    /// Remember `item`. Returns whether it's new to us.
+    pub fn insert(&mut self, item: T) -> bool {
+        self.seen.insert(item)
+    }
+
+    /// Remember everything in `items`, and hand back only what's new to
+    /// us (in order, without repeats), e.g. what a `sync` needs passing on.
+    pub fn insert_all_returning_new(&mut self, items: impl IntoIterator<Item = T>) -> Vec<T>
+    where
+        T: Clone,
+    {
+        items
+            .into_iter()
+            .filter(|item| self.seen.insert(item.clone()))
+            .collect()
+    }
+
+    /// Remember `item`, and acknowledge the envelope it came in,
+    /// whether or not we've seen it before. Only new items need
+    /// to be passed on, which the outcome tells.
+    pub fn insert_or_ack<M: Serialize>(
+        &mut self,
+        item: T,
+        envelope: &Envelope<M>,
+        ack: M,
+    ) -> InsertOutcome {
+        envelope.reply(ack).send();
+        if self.insert(item) {
+            InsertOutcome::New
+        } else {
+            InsertOutcome::AlreadySeen
+        }
+    }
+
+    pub fn contains(&self, item: &T) -> bool {
+        self.seen.contains(item)
+    }
+
+    /// Forget whatever `hash` (already rebalanced, see
+    /// [ConsistentHash::rebalance]) now has somebody other than `us` owning,
+    /// and hand it back by new owner, e.g. for them to take over deduplicating.
+    pub fn rebalance(&mut self, hash: &ConsistentHash, us: &NodeId) -> HashMap<NodeId, Vec<T>>
+    where
+        T: Clone + StableHash,
+    {
+        let mut moved: HashMap<NodeId, Vec<T>> = HashMap::new();
+        self.seen.retain(|item| match hash.owner_of(item) {
+            Some(owner) if owner != us => {
+                moved.entry(owner.clone()).or_default().push(item.clone());
+                false
+            }
+            _ => true,
+        });
+        moved
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.seen.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+/// Remembers what we've seen recently, to catch duplicates without
+/// growing forever over a long run. Anything older than `ttl`, or
+/// beyond the newest `capacity` items, is forgotten.
+#[derive(Debug)]
+pub struct SeenWindow<T, C = SystemClock, S = RandomState> {
+    ttl: Duration,
+    capacity: usize,
+    clock: C,
+    seen: HashSet<T, S>,
+    /// Oldest first.
+    order: VecDeque<(Instant, T)>,
+}
+
+impl<T: Hash + Eq + Clone> SeenWindow<T> {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self::with_clock(ttl, capacity, SystemClock)
+    }
+}
+
+impl<T: Hash + Eq + Clone, C: Clock> SeenWindow<T, C> {
+    pub fn with_clock(ttl: Duration, capacity: usize, clock: C) -> Self {
+        Self::with_clock_and_hasher(ttl, capacity, clock, RandomState::new())
+    }
+}
+
+impl<T: Hash + Eq + Clone, C: Clock, S: BuildHasher> SeenWindow<T, C, S> {
+    /// Like [SeenWindow::with_clock], but hashing with `hasher` (see [SeenSet]).
+    pub fn with_clock_and_hasher(ttl: Duration, capacity: usize, clock: C, hasher: S) -> Self {
+        Self {
+            ttl,
+            capacity,
+            clock,
+            seen: HashSet::with_hasher(hasher),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Remember `item`. Whether we hadn't seen it
+    /// (or had forgotten about it) is returned.
+    pub fn insert(&mut self, item: T) -> bool {
+        self.evict();
+        if self.seen.contains(&item) {
+            return false;
+        }
+        self.seen.insert(item.clone());
+        self.order.push_back((self.clock.now(), item));
+        self.evict();
+        true
+    }
+
+    pub fn contains(&mut self, item: &T) -> bool {
+        self.evict();
+        self.seen.contains(item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    fn evict(&mut self) {
+        let now = self.clock.now();
+        while let Some((seen_at, _)) = self.order.front() {
+            if self.order.len() <= self.capacity && now.duration_since(*seen_at) < self.ttl {
+                break;
+            }
+            let (_, item) = self.order.pop_front().unwrap();
+            self.seen.remove(&item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capture::capturing, drain_outbound, ManualClock};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn window_takes_things_back_after_their_ttl() {
+        let clock = ManualClock::new();
+        let mut window = SeenWindow::with_clock(Duration::from_secs(10), 100, clock.clone());
+        assert!(window.insert(1));
+        clock.advance(Duration::from_secs(9));
+        assert!(!window.insert(1), "still a duplicate");
+        assert!(window.insert(2));
+
+        clock.advance(Duration::from_secs(1));
+        assert!(!window.contains(&1));
+        assert!(window.insert(1), "seen too long ago to count");
+        assert!(window.contains(&2));
+        assert_eq!(window.len(), 2);
+    }
+
+    #[test]
+    fn window_forgets_the_oldest_past_its_capacity() {
+        let mut window = SeenWindow::with_clock(Duration::from_secs(10), 3, ManualClock::new());
+        for item in 1..=4 {
+            assert!(window.insert(item));
+        }
+        assert_eq!(window.len(), 3);
+        assert!(!window.contains(&1));
+        assert!((2..=4).all(|item| window.contains(&item)));
+    }
+
+    #[test]
+    fn rebalance_hands_off_what_we_no_longer_own() {
+        let before: Vec<NodeId> = ["n1", "n2"].map(NodeId::from).to_vec();
+        let after: Vec<NodeId> = ["n1", "n2", "n3"].map(NodeId::from).to_vec();
+        let us = NodeId::from("n1");
+        let mut hash = ConsistentHash::new(&before);
+        let ours: Vec<usize> = (0..1_000)
+            .filter(|key| hash.owner_of(key) == Some(&us))
+            .collect();
+        let mut seen = SeenSet::new();
+        for key in &ours {
+            seen.insert(*key);
+        }
+
+        let handoffs = hash.rebalance(&after, ours.iter().copied());
+        let mut moved = seen.rebalance(&hash, &us);
+
+        assert_eq!(moved.keys().collect::<Vec<_>>(), ["n3"]);
+        let mut handed: Vec<usize> = moved.remove("n3").unwrap();
+        handed.sort();
+        assert_eq!(
+            handed,
+            handoffs
+                .iter()
+                .map(|handoff| handoff.key)
+                .collect::<Vec<_>>()
+        );
+        assert!(!handed.is_empty());
+        assert_eq!(seen.len() + handed.len(), ours.len());
+        assert!(handed.iter().all(|key| !seen.contains(key)));
+    }
+
+    #[test]
+    fn insert_all_returns_only_whats_new() {
+        let mut seen = SeenSet::new();
+        assert_eq!(seen.insert_all_returning_new([1, 2, 3]), [1, 2, 3]);
+        assert_eq!(seen.insert_all_returning_new([5, 2, 4, 5, 3, 6]), [5, 4, 6]);
+        assert!(seen.insert_all_returning_new([1, 6]).is_empty());
+        assert_eq!(seen.len(), 6);
+    }
+
+    #[test]
+    fn insert_or_ack_always_acks_but_only_inserts_once() {
+        let _capture = capturing();
+        let mut seen = SeenSet::new();
+        let broadcast = |client| Envelope::new(client, "n1", None, json!({"type": "broadcast"}));
+        let ok = || json!({"type": "broadcast_ok"});
+        let acked = |request: &Envelope<Value>| {
+            let sent = drain_outbound();
+            assert_eq!(sent.len(), 1);
+            assert_eq!(sent[0].dest, request.src);
+            assert_eq!(sent[0].in_reply_to(), request.msg_id());
+            assert_eq!(sent[0].message(), &ok());
+        };
+
+        let first = broadcast("c1");
+        assert_eq!(seen.insert_or_ack(7, &first, ok()), InsertOutcome::New);
+        acked(&first);
+        assert!(seen.contains(&7));
+
+        let again = broadcast("c2");
+        assert_eq!(
+            seen.insert_or_ack(7, &again, ok()),
+            InsertOutcome::AlreadySeen
+        );
+        acked(&again);
+        assert_eq!(seen.len(), 1);
+    }
+}
@@ -0,0 +1,153 @@
+use crate::Address;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::RangeInclusive,
+};
+
+/// A message stamped with its sender's sequence number for the
+/// destination, so the destination can notice what went missing.
+///
+/// Use it as (part of) a message type, the fields sit right
+/// next to the message's own in the body.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sequenced<M> {
+    pub seq: u64,
+    /// Everything up to (and including) this seq made it to the sender.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ack: Option<u64>,
+    #[serde(flatten)]
+    pub message: M,
+}
+
+/// Hands out sequence numbers, counting up from 1 separately for each destination.
+#[derive(Debug, Clone, Default)]
+pub struct Sequencer {
+    last: HashMap<Address, u64>,
+}
+
+impl Sequencer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn next_for(&mut self, dest: &Address) -> u64 {
+        let last = self.last.entry(dest.clone()).or_default();
+        *last += 1;
+        *last
+    }
+}
+
+/// Notices gaps in the sequence numbers each sender stamps its messages
+/// with, so we can ask for whatever went missing to be sent again.
+#[derive(Debug, Clone, Default)]
+pub struct GapDetector {
+    senders: HashMap<Address, Received>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Received {
+    /// We've got everything up to (and including) this.
+    contiguous: u64,
+    /// What we've got past `contiguous`.
+    ahead: BTreeSet<u64>,
+}
+
+impl GapDetector {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Take note of `seq` from `src`. Returns whether it's new to us.
+    pub fn receive(&mut self, src: &Address, seq: u64) -> bool {
+        let received = self.senders.entry(src.clone()).or_default();
+        if seq <= received.contiguous || !received.ahead.insert(seq) {
+            return false;
+        }
+        while received.ahead.remove(&(received.contiguous + 1)) {
+            received.contiguous += 1;
+        }
+        true
+    }
+
+    /// Everything up to here from `src` made it, i.e. what to `ack`.
+    pub fn contiguous(&self, src: &Address) -> u64 {
+        self.senders
+            .get(src)
+            .map(|received| received.contiguous)
+            .unwrap_or_default()
+    }
+
+    /// What we know we're missing from `src`: the gaps
+    /// below the highest seq we've had from them.
+    pub fn nacks(&self, src: &Address) -> Vec<RangeInclusive<u64>> {
+        let Some(received) = self.senders.get(src) else {
+            return Vec::new();
+        };
+        let mut missing = Vec::new();
+        let mut expected = received.contiguous + 1;
+        for &seq in received.ahead.iter() {
+            if seq > expected {
+                missing.push(expected..=seq - 1);
+            }
+            expected = seq + 1;
+        }
+        missing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_skipped_seq_gets_nacked() {
+        let mut gaps = GapDetector::new();
+        let n2 = Address::from("n2");
+        for seq in [1, 2, 4] {
+            assert!(gaps.receive(&n2, seq));
+        }
+        assert_eq!(gaps.contiguous(&n2), 2);
+        assert_eq!(gaps.nacks(&n2), [3..=3]);
+
+        assert!(gaps.receive(&n2, 3));
+        assert_eq!(gaps.contiguous(&n2), 4);
+        assert!(gaps.nacks(&n2).is_empty());
+        assert!(!gaps.receive(&n2, 3), "a resend we already had");
+    }
+
+    #[test]
+    fn senders_are_tracked_apart() {
+        let mut sequencer = Sequencer::new();
+        let (n2, n3) = (Address::from("n2"), Address::from("n3"));
+        assert_eq!(sequencer.next_for(&n2), 1);
+        assert_eq!(sequencer.next_for(&n2), 2);
+        assert_eq!(sequencer.next_for(&n3), 1);
+
+        let mut gaps = GapDetector::new();
+        gaps.receive(&n2, 5);
+        assert_eq!(gaps.nacks(&n2), [1..=4]);
+        assert!(gaps.nacks(&n3).is_empty());
+    }
+
+    #[test]
+    fn seq_and_ack_sit_next_to_the_message() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        #[serde(tag = "type", rename_all = "snake_case")]
+        enum Message {
+            Sync { messages: Vec<usize> },
+        }
+
+        let json = r#"{"seq":3,"ack":1,"type":"sync","messages":[1]}"#;
+        let sequenced: Sequenced<Message> = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            sequenced,
+            Sequenced {
+                seq: 3,
+                ack: Some(1),
+                message: Message::Sync { messages: vec![1] }
+            }
+        );
+        assert_eq!(serde_json::to_string(&sequenced).unwrap(), json);
+    }
+}
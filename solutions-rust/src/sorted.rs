@@ -0,0 +1,57 @@
+use serde::{Serialize, Serializer};
+
+/// Write out any collection as an array sorted ascending, e.g. a
+/// `HashSet` of broadcast messages, so reads come out the same every time.
+///
+/// Opt in per field, the default (unsorted) path stays as fast as it was:
+///
+/// ```text
+/// ReadOk {
+///     #[serde(serialize_with = "maelstrom::sorted")]
+///     messages: HashSet<usize>,
+/// }
+/// ```
+pub fn sorted<'a, C, T, S>(items: &'a C, serializer: S) -> Result<S::Ok, S::Error>
+where
+    &'a C: IntoIterator<Item = &'a T>,
+    T: Ord + Serialize + 'a,
+    S: Serializer,
+{
+    let mut items: Vec<&T> = items.into_iter().collect();
+    items.sort_unstable();
+    serializer.collect_seq(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::HashSet;
+
+    #[derive(Serialize)]
+    struct ReadOk {
+        #[serde(serialize_with = "sorted")]
+        messages: HashSet<usize>,
+    }
+
+    #[derive(Deserialize)]
+    struct Decoded {
+        messages: Vec<usize>,
+    }
+
+    #[test]
+    fn messages_come_out_sorted_and_all_there() {
+        let messages: HashSet<usize> = [42, 7, 1000, 0, 13, 8].into();
+        let json = serde_json::to_string(&ReadOk {
+            messages: messages.clone(),
+        })
+        .unwrap();
+        assert_eq!(json, r#"{"messages":[0,7,8,13,42,1000]}"#);
+
+        let decoded: Decoded = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            decoded.messages.into_iter().collect::<HashSet<_>>(),
+            messages
+        );
+    }
+}
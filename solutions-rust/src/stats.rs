@@ -0,0 +1,84 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+/// Running totals of what went in and out of this node, as a cheap,
+/// local stand-in for Maelstrom's messages-per-op numbers.
+///
+/// Only kept track of with the `stats` feature on, in which case
+/// a one-line summary goes to stderr once stdin runs dry.
+#[derive(Debug)]
+pub struct Statistics {
+    inbound: AtomicUsize,
+    outbound: AtomicUsize,
+    bytes_out: AtomicUsize,
+    started_at: OnceLock<Instant>,
+}
+
+static STATISTICS: Statistics = Statistics {
+    inbound: AtomicUsize::new(0),
+    outbound: AtomicUsize::new(0),
+    bytes_out: AtomicUsize::new(0),
+    started_at: OnceLock::new(),
+};
+
+impl Statistics {
+    /// The totals for this process.
+    pub fn global() -> &'static Statistics {
+        &STATISTICS
+    }
+
+    /// Start the clock, if it isn't running already.
+    pub fn start(&self) {
+        self.started_at.get_or_init(Instant::now);
+    }
+
+    pub fn record_inbound(&self) {
+        self.inbound.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an outbound message that took `bytes` to write out.
+    pub fn record_outbound(&self, bytes: usize) {
+        self.outbound.fetch_add(1, Ordering::Relaxed);
+        self.bytes_out.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn inbound(&self) -> usize {
+        self.inbound.load(Ordering::Relaxed)
+    }
+
+    pub fn outbound(&self) -> usize {
+        self.outbound.load(Ordering::Relaxed)
+    }
+
+    /// How many bytes (newlines included) we've written to stdout.
+    pub fn bytes_out(&self) -> usize {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    /// How long since [Statistics::start].
+    pub fn uptime(&self) -> Duration {
+        self.started_at
+            .get()
+            .map(Instant::elapsed)
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "inbound={} outbound={} bytes_out={} uptime={:?}",
+            self.inbound(),
+            self.outbound(),
+            self.bytes_out(),
+            self.uptime()
+        )
+    }
+}
@@ -0,0 +1,98 @@
+use crate::Direction;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+static TAP: Mutex<Option<File>> = Mutex::new(None);
+
+/// Start copying every line we read or write to the file at `path`
+/// (appending to it), or stop, if there's no path.
+pub(crate) fn open(path: Option<&Path>) -> io::Result<()> {
+    let file = path
+        .map(|path| OpenOptions::new().create(true).append(true).open(path))
+        .transpose()?;
+    *TAP.lock().unwrap() = file;
+    Ok(())
+}
+
+/// Copy `line` to the tap, as `IN <micros since epoch> <line>`
+/// (or `OUT ...`), if there's one.
+pub(crate) fn record_line(direction: Direction, line: &[u8]) {
+    let mut tap = TAP.lock().unwrap();
+    let Some(file) = tap.as_mut() else {
+        return;
+    };
+    let prefix = match direction {
+        Direction::Received => "IN",
+        Direction::Sent => "OUT",
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros();
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+    let written = write!(file, "{prefix} {timestamp} ")
+        .and_then(|_| file.write_all(line))
+        .and_then(|_| file.write_all(b"\n"));
+    if let Err(err) = written {
+        // Don't let a broken tap take the node down with it.
+        eprintln!("Failed to write to the tap, closing it: {err}");
+        *tap = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Envelope;
+    use serde_json::json;
+
+    #[test]
+    fn the_tap_has_both_directions_in_order() {
+        let _capture = crate::capture::capturing();
+        let path = crate::temp_path("tap.log");
+        let _ = std::fs::remove_file(&path);
+        open(Some(&path)).unwrap();
+
+        let request =
+            r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"tapped"}}"#;
+        record_line(Direction::Received, format!("{request}\r\n").as_bytes());
+        Envelope::new(
+            "n1",
+            "c1",
+            Some(1),
+            json!({"type": "echo_ok", "echo": "tapped"}),
+        )
+        .send();
+        record_line(Direction::Received, request.as_bytes());
+        open(None).unwrap();
+        // Nothing after it's closed.
+        record_line(Direction::Received, request.as_bytes());
+
+        let tapped = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        // Anybody else sending right now (without capturing) gets tapped too.
+        let lines: Vec<(&str, u128, &str)> = tapped
+            .lines()
+            .filter(|line| line.contains("tapped"))
+            .map(|line| {
+                let mut parts = line.splitn(3, ' ');
+                let direction = parts.next().unwrap();
+                let timestamp = parts.next().unwrap().parse().unwrap();
+                (direction, timestamp, parts.next().unwrap())
+            })
+            .collect();
+        let directions: Vec<_> = lines.iter().map(|(direction, ..)| *direction).collect();
+        assert_eq!(directions, ["IN", "OUT", "IN"]);
+        assert_eq!(lines[0].2, request);
+        assert!(lines[1].2.contains(r#""type":"echo_ok""#), "{}", lines[1].2);
+        assert_eq!(lines[2].2, request);
+        assert!(lines.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+    }
+}
@@ -0,0 +1,187 @@
+use crate::NodeId;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// How to connect the cluster up when nobody
+/// tells us (or hasn't told us yet) how to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TopologyStrategy {
+    /// Everyone is everyone else's neighbor.
+    #[default]
+    FullMesh,
+    /// Everyone's neighbors are the nodes right before
+    /// and after them, by id, wrapping around.
+    Ring,
+    /// Nobody has any neighbors.
+    None,
+}
+
+/// Who sends to whom. Links only go one way: a node
+/// forwards to its neighbors, not the other way around.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Topology {
+    neighbors: BTreeMap<NodeId, Vec<NodeId>>,
+}
+
+impl Topology {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Work out every node's neighbors with `neighbors_of`, e.g. when
+    /// we build our own overlay instead of using the one we were given.
+    pub fn from_fn<F>(node_ids: &[NodeId], mut neighbors_of: F) -> Self
+    where
+        F: FnMut(&NodeId) -> Vec<NodeId>,
+    {
+        Self {
+            neighbors: node_ids
+                .iter()
+                .map(|node_id| (node_id.clone(), neighbors_of(node_id)))
+                .collect(),
+        }
+    }
+
+    /// Connect `node_ids` up according to `strategy`.
+    pub fn with_strategy(node_ids: &[NodeId], strategy: TopologyStrategy) -> Self {
+        let mut node_ids = node_ids.to_vec();
+        node_ids.sort();
+        let len = node_ids.len();
+
+        Self::from_fn(&node_ids, |node_id| match strategy {
+            TopologyStrategy::FullMesh => node_ids
+                .iter()
+                .filter(|&other| other != node_id)
+                .cloned()
+                .collect(),
+            TopologyStrategy::Ring => {
+                let position = node_ids.binary_search(node_id).unwrap();
+                let mut neighbors: Vec<NodeId> = [(position + len - 1) % len, (position + 1) % len]
+                    .into_iter()
+                    .filter(|&other| other != position)
+                    .map(|other| node_ids[other].clone())
+                    .collect();
+                neighbors.dedup();
+                neighbors
+            }
+            TopologyStrategy::None => Vec::new(),
+        })
+    }
+
+    pub fn insert(&mut self, node_id: NodeId, neighbors: Vec<NodeId>) {
+        self.neighbors.insert(node_id, neighbors);
+    }
+
+    /// Whether `node_id` has any say in the topology, even if
+    /// it's to say it has no neighbors.
+    pub fn contains(&self, node_id: &NodeId) -> bool {
+        self.neighbors.contains_key(node_id)
+    }
+
+    /// Who `node_id` sends to, if it's in the topology at all.
+    pub fn neighbors(&self, node_id: &NodeId) -> &[NodeId] {
+        self.neighbors
+            .get(node_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Every node that shows up anywhere, sorted by id.
+    pub fn nodes(&self) -> BTreeSet<&NodeId> {
+        self.neighbors
+            .iter()
+            .flat_map(|(node_id, neighbors)| std::iter::once(node_id).chain(neighbors))
+            .collect()
+    }
+
+    /// Whether a message that starts at any node can make its way to
+    /// every other node, i.e. whether broadcasts will ever converge.
+    pub fn is_connected(&self) -> bool {
+        let nodes = self.nodes();
+        let Some(&start) = nodes.first() else {
+            return true;
+        };
+
+        let mut reversed: BTreeMap<&NodeId, Vec<&NodeId>> = BTreeMap::new();
+        for (node_id, neighbors) in self.neighbors.iter() {
+            for neighbor in neighbors {
+                reversed.entry(neighbor).or_default().push(node_id);
+            }
+        }
+
+        // Everyone is reachable from `start`, and
+        // `start` is reachable from everyone.
+        let reachable =
+            Self::reachable_from(start, |node_id| self.neighbors(node_id).iter().collect());
+        let reaching = Self::reachable_from(start, |node_id| {
+            reversed.get(node_id).cloned().unwrap_or_default()
+        });
+
+        reachable.len() == nodes.len() && reaching.len() == nodes.len()
+    }
+
+    fn reachable_from<'a, F>(start: &'a NodeId, next: F) -> BTreeSet<&'a NodeId>
+    where
+        F: Fn(&'a NodeId) -> Vec<&'a NodeId>,
+    {
+        let mut seen = BTreeSet::from([start]);
+        let mut to_visit = vec![start];
+        while let Some(node_id) = to_visit.pop() {
+            for neighbor in next(node_id) {
+                if seen.insert(neighbor) {
+                    to_visit.push(neighbor);
+                }
+            }
+        }
+        seen
+    }
+}
+
+impl From<HashMap<NodeId, Vec<NodeId>>> for Topology {
+    fn from(neighbors: HashMap<NodeId, Vec<NodeId>>) -> Self {
+        Self {
+            neighbors: neighbors.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<NodeId> {
+        ids.iter().map(|&id| id.into()).collect()
+    }
+
+    #[test]
+    fn two_islands_arent_connected() {
+        let mut topology = Topology::new();
+        topology.insert("n1".into(), ids(&["n2"]));
+        topology.insert("n2".into(), ids(&["n1"]));
+        topology.insert("n3".into(), ids(&["n4"]));
+        topology.insert("n4".into(), ids(&["n3"]));
+        assert!(!topology.is_connected());
+
+        topology.insert("n2".into(), ids(&["n1", "n3"]));
+        topology.insert("n3".into(), ids(&["n2", "n4"]));
+        assert!(topology.is_connected());
+    }
+
+    #[test]
+    fn links_only_go_one_way() {
+        // n3 hears from everyone, but never tells anyone anything.
+        let mut topology = Topology::new();
+        topology.insert("n1".into(), ids(&["n2", "n3"]));
+        topology.insert("n2".into(), ids(&["n1", "n3"]));
+        topology.insert("n3".into(), Vec::new());
+        assert!(!topology.is_connected());
+    }
+
+    #[test]
+    fn the_strategies_are_connected_except_none() {
+        let nodes = ids(&["n0", "n1", "n2", "n3", "n4"]);
+        assert!(Topology::with_strategy(&nodes, TopologyStrategy::FullMesh).is_connected());
+        assert!(Topology::with_strategy(&nodes, TopologyStrategy::Ring).is_connected());
+        assert!(!Topology::with_strategy(&nodes, TopologyStrategy::None).is_connected());
+        assert!(Topology::new().is_connected());
+    }
+}
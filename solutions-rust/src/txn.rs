@@ -0,0 +1,129 @@
+use serde::{de, ser::SerializeTuple, Deserialize, Deserializer, Serialize, Serializer};
+
+/// A single operation in a `txn`, which Maelstrom
+/// writes out as an array instead of an object:
+///
+/// ```text
+/// ["r", 1, null]  // read key 1
+/// ["r", 1, 42]    // read key 1, and it was 42 (in a txn_ok)
+/// ["w", 1, 42]    // write 42 to key 1
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TxnOp {
+    /// A read, whose value is `None` until we fill it in for the reply.
+    Read {
+        key: u64,
+        value: Option<u64>,
+    },
+    Write {
+        key: u64,
+        value: u64,
+    },
+}
+
+impl TxnOp {
+    pub fn key(&self) -> u64 {
+        match self {
+            Self::Read { key, .. } | Self::Write { key, .. } => *key,
+        }
+    }
+
+    /// The same op, with a read's value set to what we read,
+    /// ready to go back in a `txn_ok`. Writes stay as they are.
+    pub fn with_read_value(self, value: Option<u64>) -> Self {
+        match self {
+            Self::Read { key, .. } => Self::Read { key, value },
+            write => write,
+        }
+    }
+}
+
+impl Serialize for TxnOp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(3)?;
+        match self {
+            Self::Read { key, value } => {
+                tuple.serialize_element("r")?;
+                tuple.serialize_element(key)?;
+                tuple.serialize_element(value)?;
+            }
+            Self::Write { key, value } => {
+                tuple.serialize_element("w")?;
+                tuple.serialize_element(key)?;
+                tuple.serialize_element(value)?;
+            }
+        }
+        tuple.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for TxnOp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (op, key, value) = <(String, u64, Option<u64>)>::deserialize(deserializer)?;
+        match (op.as_str(), value) {
+            ("r", value) => Ok(Self::Read { key, value }),
+            ("w", Some(value)) => Ok(Self::Write { key, value }),
+            ("w", None) => Err(de::Error::custom("a write needs a value")),
+            (other, _) => Err(de::Error::unknown_variant(other, &["r", "w"])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maelstrom_txns_round_trip() {
+        let json = r#"[["r",1,null],["w",1,42],["r",2,7]]"#;
+        let txn: Vec<TxnOp> = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            txn,
+            [
+                TxnOp::Read {
+                    key: 1,
+                    value: None
+                },
+                TxnOp::Write { key: 1, value: 42 },
+                TxnOp::Read {
+                    key: 2,
+                    value: Some(7)
+                },
+            ]
+        );
+        assert_eq!(serde_json::to_string(&txn).unwrap(), json);
+    }
+
+    #[test]
+    fn reads_get_filled_in_for_the_reply() {
+        let txn: Vec<TxnOp> =
+            serde_json::from_str(r#"[["r",1,null],["w",1,42],["r",1,null]]"#).unwrap();
+        let mut value = None;
+        let done: Vec<TxnOp> = txn
+            .into_iter()
+            .map(|op| match op {
+                TxnOp::Write { value: written, .. } => {
+                    value = Some(written);
+                    op
+                }
+                read => read.with_read_value(value),
+            })
+            .collect();
+        assert_eq!(
+            serde_json::to_string(&done).unwrap(),
+            r#"[["r",1,null],["w",1,42],["r",1,42]]"#
+        );
+    }
+
+    #[test]
+    fn bad_ops_are_rejected() {
+        for json in [
+            r#"["w",1,null]"#,
+            r#"["x",1,2]"#,
+            r#"["r",1]"#,
+            r#"{"r":1}"#,
+        ] {
+            assert!(serde_json::from_str::<TxnOp>(json).is_err(), "{json}");
+        }
+    }
+}